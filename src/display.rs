@@ -0,0 +1,137 @@
+use std::cmp;
+use std::iter;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Terminal cells taken by a grapheme cluster. Terminals draw a cluster in
+/// at most two cells, even when the widths of its chars add up to more.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    cmp::min(grapheme.width(), 2)
+}
+
+/// Terminal cells taken by a grapheme cluster drawn `start` cells into a
+/// line. A tab reaches up to the next multiple of `tab_stop`.
+fn cell_width(grapheme: &str, start: usize, tab_stop: usize) -> usize {
+    match grapheme {
+        "\t" => tab_stop - start % tab_stop,
+        _ => grapheme_width(grapheme),
+    }
+}
+
+/// Terminal cells taken by the first `column` chars of a line.
+pub fn column_width(line: &str, column: usize, tab_stop: usize) -> usize {
+    let mut chars = 0;
+    let mut width = 0;
+    for grapheme in line.graphemes(true) {
+        if chars >= column {
+            break;
+        }
+        chars += grapheme.chars().count();
+        width += cell_width(grapheme, width, tab_stop);
+    }
+    width
+}
+
+/// Char column of the last grapheme cluster boundary of a line that fits
+/// in `width` cells.
+pub fn column_at_width(line: &str, width: usize, tab_stop: usize) -> usize {
+    let mut chars = 0;
+    let mut used = 0;
+    for grapheme in line.graphemes(true) {
+        used += cell_width(grapheme, used, tab_stop);
+        if used > width {
+            break;
+        }
+        chars += grapheme.chars().count();
+    }
+    chars
+}
+
+/// Number of grapheme clusters in the first `column` chars of a line.
+pub fn grapheme_column(line: &str, column: usize) -> usize {
+    let mut chars = 0;
+    line.graphemes(true)
+        .take_while(|grapheme| {
+            chars += grapheme.chars().count();
+            chars <= column
+        })
+        .count()
+}
+
+/// The part of a line shown in `width` cells when scrolled `offset` cells
+/// to the right. Tabs show as spaces, as does a wide cluster cut by the
+/// left edge.
+pub fn visible(line: &str, offset: usize, width: usize, tab_stop: usize) -> String {
+    let mut visible = String::new();
+    let mut start = 0;
+    for grapheme in line.graphemes(true) {
+        let end = start + cell_width(grapheme, start, tab_stop);
+        if end > offset + width {
+            break;
+        }
+        if grapheme == "\t" {
+            let from = cmp::max(start, offset);
+            visible.extend(iter::repeat_n(' ', end.saturating_sub(from)));
+        } else if start >= offset {
+            visible.push_str(grapheme);
+        } else if end > offset {
+            visible.extend(iter::repeat_n(' ', end - offset));
+        }
+        start = end;
+    }
+    visible
+}
+
+/// `visible` split where the chars of `range` start and end, so the part
+/// in between can be drawn differently.
+pub fn visible_parts(
+    line: &str,
+    range: Range<usize>,
+    offset: usize,
+    width: usize,
+    tab_stop: usize,
+) -> [String; 3] {
+    let visible_to = |column: usize| {
+        let prefix: String = line.chars().take(column).collect();
+        visible(&prefix, offset, width, tab_stop)
+    };
+    let before = visible_to(range.start);
+    let through = visible_to(range.end);
+    let all = visible(line, offset, width, tab_stop);
+    let inside = through.get(before.len()..).unwrap_or_default().to_string();
+    let after = all.get(through.len()..).unwrap_or_default().to_string();
+    [before, inside, after]
+}
+
+/// Char columns at which the rows of a line start when it is wrapped to
+/// rows of `width` cells, breaking after whitespace where possible.
+pub fn wrap(line: &str, width: usize, tab_stop: usize) -> Vec<usize> {
+    let mut rows = vec![0];
+    let mut chars = 0;
+    let mut used = 0;
+    // INFO: chars and cells up to the last whitespace on the current row
+    let mut breakpoint = None;
+    for grapheme in line.graphemes(true) {
+        let mut cells = cell_width(grapheme, used, tab_stop);
+        if used + cells > width && used > 0 {
+            match breakpoint.take() {
+                Some((at, before)) => {
+                    rows.push(at);
+                    used -= before;
+                }
+                None => {
+                    rows.push(chars);
+                    used = 0;
+                }
+            }
+            cells = cell_width(grapheme, used, tab_stop);
+        }
+        chars += grapheme.chars().count();
+        used += cells;
+        if grapheme.chars().all(char::is_whitespace) {
+            breakpoint = Some((chars, used));
+        }
+    }
+    rows
+}
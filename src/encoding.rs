@@ -0,0 +1,250 @@
+use crate::piece_table::PieceTable;
+use crate::text_buffer::{Backend, TextBuffer};
+use encoding_rs::{EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str;
+
+/// Bytes looked at to guess the encoding of a file.
+const SAMPLE: u64 = 64 << 10;
+
+/// Encoding a file is read and written in, and whether it starts with a
+/// byte order mark.
+#[derive(Copy, Clone, PartialEq)]
+pub struct FileEncoding {
+    encoding: &'static Encoding,
+    bom: bool,
+}
+
+impl FileEncoding {
+    pub const UTF_8: FileEncoding = FileEncoding {
+        encoding: UTF_8,
+        bom: false,
+    };
+
+    /// Guesses the encoding of a file from its first bytes: a byte order
+    /// mark, then UTF-16 without one, then UTF-8, falling back to
+    /// Windows-1252 since every byte sequence is valid in it.
+    fn detect(bytes: &[u8]) -> FileEncoding {
+        if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+            return FileEncoding {
+                encoding,
+                bom: true,
+            };
+        }
+        let encoding = match utf_16_without_bom(bytes) {
+            Some(encoding) => encoding,
+            // INFO: a sample may end in the middle of a char
+            None => match str::from_utf8(bytes) {
+                Ok(_) => UTF_8,
+                Err(error) if error.error_len().is_none() => UTF_8,
+                Err(_) => WINDOWS_1252,
+            },
+        };
+        FileEncoding {
+            encoding,
+            bom: false,
+        }
+    }
+
+    /// Parses an encoding label like `latin1` or `utf-16be`. A `-bom`
+    /// suffix adds a byte order mark to UTF-8, UTF-16 always gets one.
+    pub fn from_label(label: &str) -> Option<FileEncoding> {
+        let label = label.trim().to_ascii_lowercase();
+        let (label, bom) = match label.strip_suffix("-bom") {
+            Some(label) => (label, true),
+            None => (label.as_str(), false),
+        };
+        let encoding = Encoding::for_label(label.as_bytes())?;
+        let utf_16 = encoding == UTF_16LE || encoding == UTF_16BE;
+        // INFO: encodings like `replacement` can only be decoded
+        if !utf_16 && encoding.output_encoding() != encoding {
+            return None;
+        }
+        if bom && !utf_16 && encoding != UTF_8 {
+            return None;
+        }
+        Some(FileEncoding {
+            encoding,
+            bom: bom || utf_16,
+        })
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        let bytes = if self.bom {
+            &bytes[self.bom_bytes().len()..]
+        } else {
+            bytes
+        };
+        self.encoding
+            .decode_without_bom_handling(bytes)
+            .0
+            .into_owned()
+    }
+
+    fn bom_bytes(&self) -> &'static [u8] {
+        match self.encoding {
+            encoding if encoding == UTF_16LE => b"\xFF\xFE",
+            encoding if encoding == UTF_16BE => b"\xFE\xFF",
+            _ => b"\xEF\xBB\xBF",
+        }
+    }
+
+    /// Writes text given as chunks of UTF-8 in this encoding. Fails on the
+    /// first char the encoding can not represent.
+    pub fn write<'a>(
+        &self,
+        out: &mut impl Write,
+        chunks: impl Iterator<Item = &'a str>,
+    ) -> io::Result<()> {
+        if self.bom {
+            out.write_all(self.bom_bytes())?;
+        }
+        if self.encoding == UTF_8 {
+            for chunk in chunks {
+                out.write_all(chunk.as_bytes())?;
+            }
+            return Ok(());
+        }
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            let big_endian = self.encoding == UTF_16BE;
+            for chunk in chunks {
+                for unit in chunk.encode_utf16() {
+                    if big_endian {
+                        out.write_all(&unit.to_be_bytes())?;
+                    } else {
+                        out.write_all(&unit.to_le_bytes())?;
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        let mut encoder = self.encoding.new_encoder();
+        let mut buffer = [0; 4096];
+        // INFO: an empty last chunk lets stateful encodings finish up
+        for (mut chunk, last) in chunks.map(|chunk| (chunk, false)).chain([("", true)]) {
+            loop {
+                let (result, read, written) =
+                    encoder.encode_from_utf8_without_replacement(chunk, &mut buffer, last);
+                out.write_all(&buffer[..written])?;
+                chunk = &chunk[read..];
+                match result {
+                    EncoderResult::InputEmpty => break,
+                    EncoderResult::OutputFull => {}
+                    EncoderResult::Unmappable(c) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("'{}' can not be written in {}", c, self),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FileEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.encoding.name())?;
+        if self.bom {
+            write!(f, " BOM")?;
+        }
+        Ok(())
+    }
+}
+
+/// Recognizes mostly ASCII UTF-16 text by the zero byte in most of its
+/// code units.
+fn utf_16_without_bom(bytes: &[u8]) -> Option<&'static Encoding> {
+    let units = bytes.len() / 2;
+    if units == 0 {
+        return None;
+    }
+    let zeros = |first: usize| bytes.chunks_exact(2).filter(|unit| unit[first] == 0).count();
+    let (high_first, high_second) = (zeros(0), zeros(1));
+    if high_second * 2 > units && high_first * 10 < units {
+        Some(UTF_16LE)
+    } else if high_first * 2 > units && high_second * 10 < units {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Opens a file in the encoding it appears to be in. Files whose start is
+/// plain UTF-8 are opened as they are in a piece table, so large ones can
+/// be mapped; others are decoded. A mapped file may still turn out not to
+/// be UTF-8 further on while it loads, see `decode` for that.
+pub fn open(path: &Path, backend: Backend) -> io::Result<(Box<dyn TextBuffer>, FileEncoding)> {
+    let mut sample = Vec::new();
+    File::open(path)?.take(SAMPLE).read_to_end(&mut sample)?;
+    if backend == Backend::PieceTable && FileEncoding::detect(&sample) == FileEncoding::UTF_8 {
+        match PieceTable::open(path) {
+            Ok(table) => return Ok((Box::new(table), FileEncoding::UTF_8)),
+            // INFO: not UTF-8 after all past the sample
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {}
+            Err(error) => return Err(error),
+        }
+    }
+    decode(path, backend)
+}
+
+/// Reads a whole file and decodes it in the encoding all of its bytes
+/// appear to be in.
+pub fn decode(path: &Path, backend: Backend) -> io::Result<(Box<dyn TextBuffer>, FileEncoding)> {
+    let bytes = fs::read(path)?;
+    let encoding = FileEncoding::detect(&bytes);
+    Ok((backend.create(encoding.decode(&bytes)), encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(bytes: &[u8]) -> String {
+        FileEncoding::detect(bytes).to_string()
+    }
+
+    #[test]
+    fn detect_trusts_a_byte_order_mark() {
+        assert_eq!(detect(b"\xEF\xBB\xBFcaf\xC3\xA9"), "UTF-8 BOM");
+        assert_eq!(detect(b"\xFF\xFEh\0i\0"), "UTF-16LE BOM");
+        assert_eq!(detect(b"\xFE\xFF\0h\0i"), "UTF-16BE BOM");
+    }
+
+    #[test]
+    fn detect_spots_utf_16_by_its_zero_bytes() {
+        assert_eq!(detect(b"h\0e\0l\0l\0o\0\n\0"), "UTF-16LE");
+        assert_eq!(detect(b"\0h\0e\0l\0l\0o\0\n"), "UTF-16BE");
+    }
+
+    #[test]
+    fn detect_prefers_utf_8_and_falls_back_to_windows_1252() {
+        assert_eq!(detect(b""), "UTF-8");
+        assert_eq!(detect(b"caf\xC3\xA9\n"), "UTF-8");
+        // INFO: a sample cut in the middle of a char is still UTF-8
+        assert_eq!(detect(b"caf\xC3"), "UTF-8");
+        assert_eq!(detect(b"caf\xE9 au lait\n"), "windows-1252");
+    }
+
+    #[test]
+    fn decoded_text_writes_back_to_the_same_bytes() {
+        for bytes in [
+            &b"\xEF\xBB\xBFcaf\xC3\xA9\n"[..],
+            b"\xFF\xFEc\0a\0f\0\xE9\0",
+            b"caf\xE9 au lait\n",
+        ] {
+            let encoding = FileEncoding::detect(bytes);
+            let text = encoding.decode(bytes);
+            let mut written = Vec::new();
+            encoding
+                .write(&mut written, [text.as_str()].into_iter())
+                .unwrap();
+            assert_eq!(written, bytes);
+        }
+    }
+}
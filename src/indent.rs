@@ -0,0 +1,95 @@
+use crate::text_buffer::TextBuffer;
+use std::cmp;
+use std::fmt;
+
+/// Lines looked at to guess the indent style of a file.
+const DETECT_LINES: usize = 1000;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Indent {
+    Tabs,
+    Spaces(usize),
+}
+
+impl Indent {
+    /// Parses `tabs` or a number of spaces.
+    pub fn from_name(name: &str) -> Option<Indent> {
+        match name {
+            "tabs" => Some(Indent::Tabs),
+            _ => name.parse().ok().filter(|&n| n > 0).map(Indent::Spaces),
+        }
+    }
+
+    /// Text that indents from `width` cells into a line to the next
+    /// indent level.
+    pub fn unit(&self, width: usize) -> String {
+        match self {
+            Indent::Tabs => String::from('\t'),
+            Indent::Spaces(n) => " ".repeat(n - width % n),
+        }
+    }
+
+    /// Chars to remove from the start of a line to take it back one
+    /// indent level: a leading tab, or the spaces down to the level before.
+    pub fn dedent(&self, line: &str, tab_stop: usize) -> usize {
+        if line.starts_with('\t') {
+            return 1;
+        }
+        let n = match self {
+            Indent::Tabs => tab_stop,
+            Indent::Spaces(n) => *n,
+        };
+        let spaces = line.chars().take_while(|&c| c == ' ').count();
+        match spaces {
+            0 => 0,
+            _ => spaces - (spaces - 1) / n * n,
+        }
+    }
+}
+
+impl fmt::Display for Indent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Indent::Tabs => write!(f, "Tabs"),
+            Indent::Spaces(n) => write!(f, "Spaces: {}", n),
+        }
+    }
+}
+
+/// The indent style of the first lines of the text, if any are indented.
+/// Space indents are as wide as the most common step between the indents
+/// of neighbouring lines.
+pub fn detect(text: &dyn TextBuffer) -> Option<Indent> {
+    let mut tabs = 0;
+    let mut spaced = 0;
+    // INFO: how often each step of 1 to 8 spaces occurs
+    let mut steps = [0; 8];
+    let mut previous = 0;
+    for line in 0..cmp::min(text.line_count(), DETECT_LINES) {
+        let line = text.line(line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with('\t') {
+            tabs += 1;
+            continue;
+        }
+        let spaces = line.chars().take_while(|&c| c == ' ').count();
+        if spaces > 0 {
+            spaced += 1;
+        }
+        let step = spaces.abs_diff(previous);
+        if (1..=steps.len()).contains(&step) {
+            steps[step - 1] += 1;
+        }
+        previous = spaces;
+    }
+    if tabs > spaced {
+        return Some(Indent::Tabs);
+    }
+    // INFO: ties go to the wider step, which odd alignment rarely hits
+    let (step, count) = (1..)
+        .zip(steps)
+        .max_by_key(|&(step, count)| (count, step))?;
+    (count > 0).then_some(Indent::Spaces(step))
+}
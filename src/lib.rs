@@ -1,8 +1,6 @@
 use crossterm::{
-    cursor::{
-        MoveLeft, MoveRight, MoveTo, MoveToColumn, RestorePosition, SavePosition, SetCursorStyle,
-    },
-    event::KeyCode,
+    cursor::{MoveTo, RestorePosition, SavePosition, SetCursorStyle},
+    event::{DisableBracketedPaste, EnableBracketedPaste, KeyCode},
     execute, queue,
     style::{Print, PrintStyledContent, Stylize},
     terminal::{
@@ -10,11 +8,30 @@ use crossterm::{
         LeaveAlternateScreen, SetTitle,
     },
 };
-use piece_table::PieceTable;
-use std::io::{stdout, Stdout, Write};
-use std::{cmp, fs, usize};
+use encoding::FileEncoding;
+use indent::Indent;
+use line_ending::LineEnding;
+use piece_table::{Edit, EditError};
+use std::cmp;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{self, stdout, BufWriter, Stdout, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use text_buffer::{Backend, TextBuffer};
+use unicode_segmentation::UnicodeSegmentation;
+
+mod display;
+mod encoding;
+mod indent;
+mod line_ending;
+pub mod piece_table;
+pub mod text_buffer;
 
-mod piece_table;
+/// Columns between tab stops unless `--tab-stop=` says otherwise.
+const TAB_STOP: usize = 4;
 
 pub enum Direction {
     Up,
@@ -23,49 +40,215 @@ pub enum Direction {
     Right,
 }
 
+enum Prompt {
+    FileName,
+    TimeTravel,
+    Encoding,
+}
+
 pub struct Editor {
     stdout: Stdout,
     file_path: Option<String>,
-    contents: PieceTable,
-    window_offset: u16,
-    column_pos: Option<u16>,
+    backend: Backend,
+    history_limit: Option<usize>,
+    contents: Box<dyn TextBuffer>,
+    encoding: FileEncoding,
+    line_ending: LineEnding,
+    mixed_endings: bool,
+    convert_endings: bool,
+    window_offset: usize,
+    column_offset: usize,
+    wrap: bool,
+    tab_stop: usize,
+    indent: Indent,
+    column_pos: Option<usize>,
     cursor_pos: CursorPosition,
+    anchor: Option<usize>,
     padding: u16,
-    custom_prompt: bool,
+    custom_prompt: Option<Prompt>,
     custom_name: Option<String>,
+    typing: bool,
+    message: Option<String>,
 }
 
 pub struct CursorPosition {
-    x: u16,
-    y: u16,
+    x: usize,
+    y: usize,
+}
+
+/// Hidden file next to `path`, named after it with an extension.
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}", file_name, extension))
+}
+
+/// Char ranges of the rows of a line of `length` chars that start at
+/// `starts`.
+fn row_ranges(starts: &[usize], length: usize) -> impl Iterator<Item = Range<usize>> + '_ {
+    let ends = starts.iter().skip(1).copied().chain([length]);
+    starts.iter().zip(ends).map(|(&start, end)| start..end)
+}
+
+/// Index of the row a char column of a line falls on.
+fn row_of(starts: &[usize], column: usize) -> usize {
+    starts.partition_point(|&start| start <= column) - 1
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Location of the undo history saved alongside a file.
+fn undo_path(file_path: &str) -> PathBuf {
+    sibling_path(Path::new(file_path), "undo")
 }
 
 impl Editor {
     pub fn build(mut args: impl Iterator<Item = String>) -> Result<Editor, std::io::Error> {
         args.next();
 
-        let file_path = args.next();
-
-        let stdout = stdout();
+        let mut backend = Backend::PieceTable;
+        let mut tab_stop = TAB_STOP;
+        let mut history_limit = None;
+        let mut indent = None;
+        let mut file_path = None;
+        for arg in args {
+            if let Some(name) = arg.strip_prefix("--backend=") {
+                backend = Backend::from_name(name)
+                    .ok_or_else(|| invalid_input(format!("Unknown backend: {}", name)))?;
+            } else if let Some(stop) = arg.strip_prefix("--tab-stop=") {
+                tab_stop = stop
+                    .parse()
+                    .ok()
+                    .filter(|&stop| stop > 0)
+                    .ok_or_else(|| invalid_input(format!("Invalid tab stop: {}", stop)))?;
+            } else if let Some(limit) = arg.strip_prefix("--history-limit=") {
+                let steps = limit
+                    .parse()
+                    .map_err(|_| invalid_input(format!("Invalid history limit: {}", limit)))?;
+                history_limit = Some(steps);
+            } else if let Some(name) = arg.strip_prefix("--indent=") {
+                let style = Indent::from_name(name)
+                    .ok_or_else(|| invalid_input(format!("Invalid indent: {}", name)))?;
+                indent = Some(style);
+            } else {
+                file_path = Some(arg);
+            }
+        }
 
-        let file_contents = match file_path {
-            Some(ref file) => fs::read_to_string(file)?,
-            None => String::from('\n'),
+        let (mut contents, encoding) = match file_path {
+            Some(ref file) => encoding::open(Path::new(file), backend)?,
+            None => (backend.create(String::from('\n')), FileEncoding::UTF_8),
         };
+        contents.set_history_limit(history_limit);
+
+        let mut editor = Editor::new(contents, encoding, file_path, tab_stop, indent);
+        editor.backend = backend;
+        editor.history_limit = history_limit;
+        if !editor.is_loading() {
+            editor.load_history();
+        }
+        Ok(editor)
+    }
+
+    /// Editor over a text buffer of any implementation, with no file and
+    /// the default settings of `build`.
+    pub fn with_buffer(contents: Box<dyn TextBuffer>) -> Editor {
+        Editor::new(contents, FileEncoding::UTF_8, None, TAB_STOP, None)
+    }
 
-        let contents = PieceTable::new(file_contents);
+    fn new(
+        contents: Box<dyn TextBuffer>,
+        encoding: FileEncoding,
+        file_path: Option<String>,
+        tab_stop: usize,
+        indent: Option<Indent>,
+    ) -> Editor {
+        let (line_ending, mixed_endings) = line_ending::detect(contents.as_ref());
+        let indent = indent
+            .or_else(|| indent::detect(contents.as_ref()))
+            .unwrap_or(Indent::Spaces(tab_stop));
 
-        Ok(Editor {
-            stdout,
+        Editor {
+            stdout: stdout(),
             file_path,
+            backend: Backend::PieceTable,
+            history_limit: None,
             contents,
+            encoding,
+            line_ending,
+            mixed_endings,
+            convert_endings: false,
             window_offset: 0,
+            column_offset: 0,
+            wrap: false,
+            tab_stop,
+            indent,
             column_pos: None,
             cursor_pos: CursorPosition { x: 0, y: 0 },
+            anchor: None,
             padding: 0,
-            custom_prompt: false,
+            custom_prompt: None,
             custom_name: None,
-        })
+            typing: false,
+            message: None,
+        }
+    }
+
+    fn load_history(&mut self) {
+        if let Some(ref file) = self.file_path {
+            // INFO: a missing or outdated undo file just means no history
+            let _ = self.contents.load_history(&undo_path(file));
+        }
+    }
+
+    /// Whether a large file is still being read in the background.
+    pub fn is_loading(&self) -> bool {
+        self.contents.is_loading()
+    }
+
+    /// Shows the text read in the background since the last call, and
+    /// loads the undo history once the whole file is in.
+    pub fn poll_loading(&mut self) {
+        match self.contents.poll_loading() {
+            Ok(false) => return,
+            Ok(true) if !self.is_loading() => self.load_history(),
+            Ok(true) => {}
+            Err(error) => self.reopen(error),
+        }
+        // INFO: a prompt owns the bottom bar, it redraws everything on close
+        if self.custom_prompt.is_none() {
+            self.render_contents();
+            self.place_cursor();
+        }
+    }
+
+    /// Reads the file again, decoding all of it, after loading it as UTF-8
+    /// stopped at `error`. The text can not be edited before it is fully
+    /// loaded, so nothing is lost.
+    fn reopen(&mut self, error: io::Error) {
+        let Some(file) = &self.file_path else {
+            return;
+        };
+        match encoding::decode(Path::new(file), self.backend) {
+            Ok((contents, encoding)) => {
+                self.contents = contents;
+                self.contents.set_history_limit(self.history_limit);
+                self.message = Some(format!("Reopened as {}, {}", encoding, error));
+                self.encoding = encoding;
+                (self.line_ending, self.mixed_endings) =
+                    line_ending::detect(self.contents.as_ref());
+                self.convert_endings = false;
+                self.typing = false;
+                self.anchor = None;
+                self.column_pos = None;
+                self.window_offset = 0;
+                self.column_offset = 0;
+                self.cursor_pos = CursorPosition { x: 0, y: 0 };
+                self.load_history();
+            }
+            Err(error) => self.message = Some(format!("Not loaded: {}", error)),
+        }
     }
 
     pub fn init(&mut self) {
@@ -77,6 +260,7 @@ impl Editor {
             MoveTo(0, 0),
         )
         .unwrap();
+        let _ = execute!(self.stdout, EnableBracketedPaste);
 
         self.render_contents();
         execute!(self.stdout, MoveTo(self.padding, 0)).unwrap();
@@ -90,31 +274,69 @@ impl Editor {
 
     pub fn render_contents(&mut self) {
         queue!(self.stdout, SavePosition).unwrap();
-        let (_, w_rows) = size().unwrap();
-        let shown_contents = self.contents.read();
+        let (w_columns, w_rows) = size().unwrap();
 
         queue!(self.stdout, Clear(ClearType::All)).unwrap();
-        let newline_count = shown_contents
-            .lines()
-            .count()
-            .checked_ilog10()
-            .expect("Invalid log of newline count");
-        let padding: usize = 1 + usize::try_from(newline_count).unwrap();
+        let line_count = self.contents.line_count();
+        let padding: usize = 1 + usize::try_from(line_count.ilog10()).unwrap();
         self.padding = padding.try_into().unwrap();
         self.padding += 2;
-        for (i, line) in shown_contents.lines().enumerate() {
-            if i >= self.window_offset.try_into().unwrap() {
+        let width = usize::from(w_columns.saturating_sub(self.padding));
+        let selection = self.selection();
+        let mut row = 0;
+        let mut line = self.window_offset;
+        while row < w_rows && line < line_count {
+            let text = self.contents.line(line);
+            let start = self.contents.line_start(line).unwrap_or_default();
+            let length = text.chars().count();
+            let starts = self.line_rows(&text);
+            for (i, range) in row_ranges(&starts, length).enumerate() {
+                if row == w_rows {
+                    break;
+                }
+                // INFO: wrapped rows after the first show an arrow instead of a number
+                let gutter = match i {
+                    0 => (line + 1).to_string(),
+                    _ => String::from("↪"),
+                };
+                let text: String = text.chars().skip(range.start).take(range.len()).collect();
+                // INFO: the selected chars of the row, counted from its start
+                let selected = selection.as_ref().map_or(0..0, |selection| {
+                    let clamp = |offset: usize| {
+                        offset.saturating_sub(start).clamp(range.start, range.end) - range.start
+                    };
+                    clamp(selection.start)..clamp(selection.end)
+                });
+                let [before, mut inside, after] = display::visible_parts(
+                    &text,
+                    selected,
+                    self.column_offset,
+                    width,
+                    self.tab_stop,
+                );
+                // INFO: a selected line break shows as one highlighted cell
+                let shown = format!("{}{}{}", before, inside, after);
+                if range.end == length
+                    && selection
+                        .as_ref()
+                        .is_some_and(|selection| selection.contains(&(start + length)))
+                    && display::column_width(&shown, usize::MAX, self.tab_stop) < width
+                {
+                    inside.push(' ');
+                }
                 queue!(
                     self.stdout,
-                    MoveTo(0, u16::try_from(i).unwrap() - self.window_offset),
-                    PrintStyledContent(format!("|{:<padding$}", i + 1).on_dark_grey()),
+                    MoveTo(0, row),
+                    PrintStyledContent(format!("|{:<padding$}", gutter).on_dark_grey()),
                     Print(" "),
-                    Print(line),
+                    Print(before),
+                    PrintStyledContent(inside.reverse()),
+                    Print(after),
                 )
                 .unwrap();
-            } else if i == (w_rows + self.window_offset).into() {
-                break;
+                row += 1;
             }
+            line += 1;
         }
 
         queue!(self.stdout, RestorePosition).unwrap();
@@ -123,16 +345,31 @@ impl Editor {
     }
 
     fn render_bottom_bar(&mut self) {
-        let (column, row) = (self.cursor_pos.x, self.cursor_pos.y);
+        let line = self.contents.line(self.current_line());
+        let column = display::grapheme_column(&line, self.cursor_pos.x);
+        let row = self.cursor_pos.y;
         let (w_columns, w_rows) = size().unwrap();
         let padding: usize = (self.padding - 2).into();
         queue!(self.stdout, SavePosition).unwrap();
+        // INFO: a message replaces the key help until the next redraw
+        let help = self.message.take().unwrap_or_else(|| {
+            String::from("Ctrl | C: quit | S: save | Z: undo | R: redo")
+        });
+        let line_ending = match (self.convert_endings, self.mixed_endings) {
+            (true, _) => format!("{} on save", self.line_ending),
+            (false, true) => String::from("Mixed"),
+            (false, false) => self.line_ending.to_string(),
+        };
         let bar = format!(
-            " {:>padding$}|{:<padding$} Ctrl | C: quit | S: save | Z: undo | R: redo",
+            " {:>padding$}|{:<padding$} {} {} {} | {}",
             row + 1 + self.window_offset,
             column,
+            self.encoding,
+            line_ending,
+            self.indent,
+            help,
         );
-        let width = usize::from(w_columns) - bar.chars().count();
+        let width = usize::from(w_columns).saturating_sub(bar.chars().count());
         execute!(
             self.stdout,
             MoveTo(0, w_rows),
@@ -145,7 +382,11 @@ impl Editor {
     fn render_custom_prompt(&mut self) {
         if let Some(c_name) = &self.custom_name {
             let (w_columns, w_rows) = size().unwrap();
-            let prompt = format!("Enter file name: {}", c_name);
+            let prompt = match self.custom_prompt {
+                Some(Prompt::TimeTravel) => format!("Go back minutes: {}", c_name),
+                Some(Prompt::Encoding) => format!("Convert to encoding: {}", c_name),
+                _ => format!("Enter file name: {}", c_name),
+            };
             let prompt_len = prompt.chars().count();
             let width: usize = usize::from(w_columns) - prompt_len;
             execute!(
@@ -153,44 +394,35 @@ impl Editor {
                 MoveTo(0, w_rows),
                 PrintStyledContent(format!("{}{:width$}", prompt, " ").on_dark_grey()),
                 MoveTo(prompt_len.try_into().unwrap(), w_rows),
-                SetTitle(format!("edito.rs: {}", c_name))
             )
             .unwrap();
+            if let Some(Prompt::FileName) = self.custom_prompt {
+                execute!(self.stdout, SetTitle(format!("edito.rs: {}", c_name))).unwrap();
+            }
         }
     }
 
     pub fn get_position(&self) -> Option<usize> {
         let (column, row) = (self.cursor_pos.x, self.cursor_pos.y + self.window_offset);
-        let mut position: Option<usize> = None;
-        let (mut pointer_col, mut pointer_row) = (0, 0);
-        let contents: Vec<char> = self.contents.read().chars().collect();
-
-        for (i, char) in contents.iter().enumerate() {
-            if pointer_col == column && pointer_row == row {
-                position = Some(i);
-                break;
-            }
-            if *char == 0xA as char {
-                (pointer_col, pointer_row) = (0, pointer_row + 1);
-            } else {
-                pointer_col += 1;
-            }
-        }
-        return position;
+        self.contents.line_col_to_offset(row, column)
     }
 
     pub fn handle_key_input(&mut self, keycode: KeyCode) {
-        if self.custom_prompt {
+        if let Some(prompt) = &self.custom_prompt {
             match keycode {
                 KeyCode::Char(c) => {
+                    if let Prompt::TimeTravel = prompt {
+                        if !c.is_ascii_digit() {
+                            return;
+                        }
+                    }
                     if let Some(c_name) = &mut self.custom_name {
                         c_name.push(c);
-                        execute!(
-                            self.stdout,
-                            PrintStyledContent(c.on_dark_grey()),
-                            SetTitle(format!("edito.rs: {}", c_name))
-                        )
-                        .unwrap();
+                        execute!(self.stdout, PrintStyledContent(c.on_dark_grey())).unwrap();
+                        if let Prompt::FileName = prompt {
+                            execute!(self.stdout, SetTitle(format!("edito.rs: {}", c_name)))
+                                .unwrap();
+                        }
                     }
                 }
                 KeyCode::Backspace => {
@@ -199,26 +431,60 @@ impl Editor {
                         self.render_custom_prompt();
                     }
                 }
-                KeyCode::Enter => {
-                    if let Some(c_name) = &self.custom_name {
-                        if c_name.chars().count() > 0 {
-                            self.file_path = Some(c_name.to_string());
-                            self.write_to_file();
-                            self.custom_prompt = false;
-                            execute!(self.stdout, RestorePosition).unwrap();
-                            self.render_contents();
+                KeyCode::Enter => match prompt {
+                    Prompt::FileName => {
+                        if let Some(c_name) = &self.custom_name {
+                            if c_name.chars().count() > 0 {
+                                self.file_path = Some(c_name.to_string());
+                                self.custom_prompt = None;
+                                self.write_to_file();
+                                execute!(self.stdout, RestorePosition).unwrap();
+                                self.render_contents();
+                            }
                         }
                     }
-                }
+                    Prompt::TimeTravel => {
+                        let minutes = self.custom_name.take().and_then(|m| m.parse().ok());
+                        self.custom_prompt = None;
+                        execute!(self.stdout, RestorePosition).unwrap();
+                        match minutes {
+                            Some(minutes) => self.time_travel(minutes),
+                            None => self.render_contents(),
+                        }
+                    }
+                    Prompt::Encoding => {
+                        let label = self.custom_name.take().unwrap_or_default();
+                        self.custom_prompt = None;
+                        execute!(self.stdout, RestorePosition).unwrap();
+                        self.convert_encoding(&label);
+                    }
+                },
                 KeyCode::Esc => {
+                    if let Prompt::FileName = prompt {
+                        execute!(self.stdout, SetTitle("edito.rs: New File")).unwrap();
+                    }
                     self.custom_name = None;
-                    self.custom_prompt = false;
-                    execute!(self.stdout, RestorePosition, SetTitle("edito.rs: New File")).unwrap();
+                    self.custom_prompt = None;
+                    execute!(self.stdout, RestorePosition).unwrap();
                     self.render_contents();
                 }
                 _ => {}
             }
         } else {
+            if !matches!(keycode, KeyCode::Char(_)) {
+                self.commit_typing();
+            }
+            // INFO: typing replaces the selection and moving drops it
+            if !matches!(
+                keycode,
+                KeyCode::Char(_)
+                    | KeyCode::Enter
+                    | KeyCode::Tab
+                    | KeyCode::Backspace
+                    | KeyCode::Delete
+            ) {
+                self.clear_selection();
+            }
             match keycode {
                 KeyCode::Left => self.move_cursor(Direction::Left),
                 KeyCode::Right => self.move_cursor(Direction::Right),
@@ -228,26 +494,59 @@ impl Editor {
                     self.write(c);
                     self.render_contents();
                 }
+                KeyCode::Tab => {
+                    let line = self.contents.line(self.current_line());
+                    let width = display::column_width(&line, self.cursor_pos.x, self.tab_stop);
+                    self.write_str(&self.indent.unit(width));
+                    self.column_pos = None;
+                    self.render_contents();
+                }
+                KeyCode::BackTab => self.dedent(),
+                KeyCode::Home => {
+                    // INFO: smart home, to the first non-blank and then the start
+                    let text = self.contents.line(self.current_line());
+                    let indent = text.chars().take_while(|c| c.is_whitespace()).count();
+                    match self.cursor_pos.x == indent {
+                        true => self.move_to_column(0),
+                        false => self.move_to_column(indent),
+                    }
+                }
+                KeyCode::End => {
+                    let length = self.contents.line_length(self.current_line());
+                    self.move_to_column(length);
+                }
+                KeyCode::PageUp => self.move_page(Direction::Up),
+                KeyCode::PageDown => self.move_page(Direction::Down),
+                KeyCode::Backspace | KeyCode::Delete if self.selection().is_some() => {
+                    self.replace_selection("");
+                    self.render_contents();
+                }
+                KeyCode::Delete => {
+                    self.anchor = None;
+                    if let Some(pos) = self.get_position() {
+                        if pos < self.contents.len() {
+                            let deleted = self.contents.delete(pos);
+                            self.edited(deleted);
+                            self.column_pos = None;
+                            self.render_contents();
+                        }
+                    }
+                }
                 KeyCode::Enter => {
-                    self.write(0x00A as char);
-                    self.move_cursor(Direction::Down);
-                    queue!(self.stdout, MoveToColumn(self.padding)).unwrap();
-                    self.column_pos = Some(0);
+                    self.write_str(self.line_ending.as_str());
+                    self.column_pos = None;
                     self.render_contents();
                 }
                 KeyCode::Backspace => {
+                    self.anchor = None;
                     if let Some(pos) = self.get_position() {
                         if pos > 0 {
-                            // INFO: adjust cursor position
-                            if self.cursor_pos.x > 0 {
-                                execute!(self.stdout, MoveLeft(1)).unwrap();
-                                self.cursor_pos.x -= 1;
-                            } else {
-                                self.column_pos = Some(8000);
-                                self.move_cursor(Direction::Up);
+                            let previous = self.contents.prev_grapheme_boundary(pos);
+                            let deleted = self.contents.delete(previous);
+                            if self.edited(deleted) {
+                                self.move_to_offset(previous);
                             }
-
-                            self.contents.delete(pos - 1);
+                            self.column_pos = None;
                             self.render_contents();
                         }
                     }
@@ -259,98 +558,542 @@ impl Editor {
 
     fn move_cursor(&mut self, direction: Direction) {
         let (column, row) = (self.cursor_pos.x, self.cursor_pos.y);
-        let text = self.contents.read();
+        let line = row + self.window_offset;
         match direction {
+            Direction::Up | Direction::Down if self.wrap => self.move_row(direction),
             Direction::Up => {
                 if row > 0 {
-                    if let None = self.column_pos {
-                        self.column_pos = Some(column);
-                    }
-                    let x = cmp::min(
-                        self.contents
-                            .get_line_length(row + self.window_offset - 1)
-                            .try_into()
-                            .unwrap(),
-                        self.column_pos
-                            .expect("Column position should not be None!"),
-                    );
-                    execute!(self.stdout, MoveTo(x + self.padding, row - 1,)).unwrap();
+                    let x = self.column_on_line(line - 1);
                     self.cursor_pos.x = x;
                     self.cursor_pos.y -= 1;
+                    self.place_cursor();
                 } else if self.window_offset > 0 {
                     self.window_offset -= 1;
                     self.render_contents();
                 }
             }
             Direction::Down => {
-                if text.lines().count() - 1 > (row + self.window_offset).into() {
-                    if let None = self.column_pos {
-                        self.column_pos = Some(column);
-                    }
-                    let x = cmp::min(
-                        self.contents
-                            .get_line_length(row + self.window_offset + 1)
-                            .try_into()
-                            .unwrap(),
-                        self.column_pos
-                            .expect("Column position should not be None!"),
-                    );
+                if self.contents.line_count() - 1 > line {
+                    let x = self.column_on_line(line + 1);
                     // INFO: checking for y bounds then stay on line and increase offset
                     let (_, w_rows) = size().unwrap();
-                    let mut new_row = row;
-                    if self.cursor_pos.y == w_rows - 2 {
+                    if self.cursor_pos.y == usize::from(w_rows - 2) {
                         self.window_offset += 1;
                         self.render_contents();
                     } else {
-                        new_row = row + 1;
                         self.cursor_pos.y += 1;
                     }
-                    execute!(self.stdout, MoveTo(x + self.padding, new_row)).unwrap();
                     self.cursor_pos.x = x;
+                    self.place_cursor();
                 }
             }
             Direction::Left => {
                 if column > 0 {
-                    execute!(self.stdout, MoveLeft(1)).unwrap();
-                    self.cursor_pos.x -= 1;
-                    self.column_pos = None;
+                    if let Some(position) = self.get_position() {
+                        let previous = self.contents.prev_grapheme_boundary(position);
+                        self.cursor_pos.x -= position - previous;
+                        self.column_pos = None;
+                        self.place_cursor();
+                    }
                 }
             }
             Direction::Right => {
-                if self.contents.get_line_length(row + self.window_offset) > column.into() {
-                    execute!(self.stdout, MoveRight(1)).unwrap();
-                    self.cursor_pos.x += 1;
-                    self.column_pos = None;
+                if self.contents.line_length(line) > column {
+                    if let Some(position) = self.get_position() {
+                        let next = self.contents.next_grapheme_boundary(position);
+                        self.cursor_pos.x += next - position;
+                        self.column_pos = None;
+                        self.place_cursor();
+                    }
                 }
             }
         }
         self.render_bottom_bar();
     }
 
-    pub fn undo(&mut self) {
-        self.contents.undo();
+    /// Chars between where the selection started and the cursor, if any.
+    fn selection(&self) -> Option<Range<usize>> {
+        let anchor = cmp::min(self.anchor?, self.contents.len());
+        let position = self.get_position()?;
+        let range = cmp::min(anchor, position)..cmp::max(anchor, position);
+        (!range.is_empty()).then_some(range)
+    }
+
+    /// Drops the selection, redrawing the text it highlighted.
+    fn clear_selection(&mut self) {
+        if self.anchor.take().is_some() {
+            self.render_contents();
+        }
+    }
+
+    /// Replaces the selection with `text` as one undo step and puts the
+    /// cursor after it. Returns whether there was a selection.
+    fn replace_selection(&mut self, text: &str) -> bool {
+        let selection = self.selection();
+        self.anchor = None;
+        let Some(range) = selection else {
+            return false;
+        };
+        self.commit_typing();
+        let start = range.start;
+        let edit = Edit {
+            range,
+            text: text.to_string(),
+        };
+        let replaced = self.contents.apply_edits(vec![edit]);
+        if self.edited(replaced) {
+            self.move_to_offset(start + text.chars().count());
+        }
+        self.column_pos = None;
+        true
+    }
+
+    /// Runs a cursor movement that extends the selection from where it
+    /// started, or from the cursor if there is none.
+    fn extend_selection(&mut self, movement: impl FnOnce(&mut Editor)) {
+        if self.custom_prompt.is_some() {
+            return;
+        }
+        let anchor = self.anchor.take().or_else(|| self.get_position());
+        movement(self);
+        // INFO: moving back onto the anchor ends the selection
+        self.anchor = anchor.filter(|&anchor| Some(anchor) != self.get_position());
         self.render_contents();
     }
 
+    /// Moves the cursor with an arrow, Home, End or page key, selecting
+    /// the text it passes over.
+    pub fn select(&mut self, keycode: KeyCode) {
+        match keycode {
+            KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Home
+            | KeyCode::End
+            | KeyCode::PageUp
+            | KeyCode::PageDown => self.extend_selection(|editor| editor.handle_key_input(keycode)),
+            _ => self.handle_key_input(keycode),
+        }
+    }
+
+    /// Moves the cursor by a word, selecting the text it passes over.
+    pub fn select_word(&mut self, direction: Direction) {
+        self.extend_selection(|editor| editor.move_word(direction));
+    }
+
+    /// Moves the cursor to the start or end of the text, selecting the
+    /// text it passes over.
+    pub fn select_to_edge(&mut self, direction: Direction) {
+        self.extend_selection(|editor| editor.move_to_edge(direction));
+    }
+
+    /// Moves the cursor to a char column of the current line.
+    fn move_to_column(&mut self, column: usize) {
+        self.cursor_pos.x = column;
+        self.column_pos = None;
+        self.place_cursor();
+        self.render_bottom_bar();
+    }
+
+    /// Scrolls the window a screen up or down, keeping the cursor on the
+    /// same row of it.
+    fn move_page(&mut self, direction: Direction) {
+        let (_, w_rows) = size().unwrap();
+        let page = usize::from(w_rows - 1);
+        let last_line = self.contents.line_count() - 1;
+        let window_offset = self.window_offset;
+        let (window_offset, line) = match direction {
+            Direction::Up => (
+                window_offset.saturating_sub(page),
+                self.current_line().saturating_sub(page),
+            ),
+            _ => {
+                let top = cmp::max(last_line.saturating_sub(page - 1), window_offset);
+                (
+                    cmp::min(window_offset + page, top),
+                    cmp::min(self.current_line() + page, last_line),
+                )
+            }
+        };
+        let x = self.column_on_line(line);
+        self.window_offset = window_offset;
+        self.cursor_pos = CursorPosition {
+            x,
+            y: line - window_offset,
+        };
+        self.render_contents();
+        self.place_cursor();
+        self.render_bottom_bar();
+    }
+
+    /// Moves the cursor one screen row up or down through wrapped lines,
+    /// keeping its cell within the row where the row allows.
+    fn move_row(&mut self, direction: Direction) {
+        let line = self.current_line();
+        let text = self.contents.line(line);
+        let column = self.cursor_pos.x;
+        let starts = self.line_rows(&text);
+        let row = row_of(&starts, column);
+        if self.column_pos.is_none() {
+            let row_text: String = text.chars().skip(starts[row]).collect();
+            let width = display::column_width(&row_text, column - starts[row], self.tab_stop);
+            self.column_pos = Some(width);
+        }
+        let (line, row) = match direction {
+            Direction::Down if row + 1 < starts.len() => (line, row + 1),
+            Direction::Down if line + 1 < self.contents.line_count() => (line + 1, 0),
+            Direction::Up if row > 0 => (line, row - 1),
+            Direction::Up if line > 0 => (line - 1, usize::MAX),
+            _ => return,
+        };
+
+        let text = self.contents.line(line);
+        let starts = self.line_rows(&text);
+        let row = cmp::min(row, starts.len() - 1);
+        let range = row_ranges(&starts, text.chars().count())
+            .nth(row)
+            .expect("Row of a line should exist");
+        let row_text: String = text.chars().skip(range.start).take(range.len()).collect();
+        let width = self.column_pos.unwrap_or_default();
+        let mut column = display::column_at_width(&row_text, width, self.tab_stop);
+        // INFO: the end of a row that wraps is the start of the next one
+        if column == range.len() && row + 1 < starts.len() {
+            let last = row_text.graphemes(true).next_back().unwrap_or_default();
+            column -= last.chars().count();
+        }
+        let start = self.contents.line_start(line).unwrap_or_default();
+        let window_offset = self.window_offset;
+        self.move_to_offset(start + range.start + column);
+        if self.window_offset != window_offset {
+            self.render_contents();
+            self.place_cursor();
+        }
+    }
+
+    /// Char columns at which the screen rows of a line start: just the
+    /// first column unless wrapping is on.
+    fn line_rows(&self, line: &str) -> Vec<usize> {
+        if !self.wrap {
+            return vec![0];
+        }
+        let (w_columns, _) = size().unwrap();
+        // INFO: one cell is kept free for the cursor at the end of a line
+        let width = usize::from(w_columns.saturating_sub(self.padding + 1));
+        display::wrap(line, cmp::max(width, 1), self.tab_stop)
+    }
+
+    /// Screen rows taken by the lines from the top of the window up to,
+    /// but not including, `line`.
+    fn rows_above(&self, line: usize) -> usize {
+        (self.window_offset..line)
+            .map(|line| self.line_rows(&self.contents.line(line)).len())
+            .sum()
+    }
+
+    /// Char column on `line` closest to the remembered screen column, so
+    /// vertical movement keeps its place across wide characters.
+    fn column_on_line(&mut self, line: usize) -> usize {
+        if self.column_pos.is_none() {
+            let current = self.contents.line(self.current_line());
+            let width = display::column_width(&current, self.cursor_pos.x, self.tab_stop);
+            self.column_pos = Some(width);
+        }
+        let width = self
+            .column_pos
+            .expect("Column position should not be None!");
+        let text = self.contents.line(line);
+        display::column_at_width(&text, width, self.tab_stop)
+    }
+
+    fn current_line(&self) -> usize {
+        self.cursor_pos.y + self.window_offset
+    }
+
+    /// Moves the terminal cursor to the cell of `cursor_pos`, scrolling
+    /// first if that cell is out of view: sideways, or down by whole lines
+    /// when wrapping.
+    fn place_cursor(&mut self) {
+        let current_line = self.current_line();
+        let line = self.contents.line(current_line);
+        let column = self.cursor_pos.x;
+        let starts = self.line_rows(&line);
+        let row = row_of(&starts, column);
+        let row_text: String = line.chars().skip(starts[row]).collect();
+        let x = display::column_width(&row_text, column - starts[row], self.tab_stop);
+        let (w_columns, w_rows) = size().unwrap();
+        let width = usize::from(w_columns.saturating_sub(self.padding)).max(1);
+        let column_offset = if x < self.column_offset {
+            x
+        } else if x >= self.column_offset + width {
+            x + 1 - width
+        } else {
+            self.column_offset
+        };
+        let mut scrolled = column_offset != self.column_offset;
+        self.column_offset = column_offset;
+        let mut y = self.rows_above(current_line) + row;
+        while y > usize::from(w_rows - 2) && self.cursor_pos.y > 0 {
+            y -= self
+                .line_rows(&self.contents.line(self.window_offset))
+                .len();
+            self.window_offset += 1;
+            self.cursor_pos.y -= 1;
+            scrolled = true;
+        }
+        if scrolled {
+            self.render_contents();
+        }
+        let x = u16::try_from(x - self.column_offset).unwrap();
+        let y = u16::try_from(y).unwrap_or(u16::MAX);
+        execute!(self.stdout, MoveTo(x + self.padding, y)).unwrap();
+    }
+
+    /// Moves the cursor to a char offset, scrolling the window if needed.
+    fn move_to_offset(&mut self, offset: usize) {
+        let (line, column) = self.contents.offset_to_line_col(offset);
+        let (_, w_rows) = size().unwrap();
+        let last_row = usize::from(w_rows - 2);
+        if line < self.window_offset {
+            self.window_offset = line;
+        } else if line > self.window_offset + last_row {
+            self.window_offset = line - last_row;
+        }
+        self.cursor_pos = CursorPosition {
+            x: column,
+            y: line - self.window_offset,
+        };
+        self.place_cursor();
+    }
+
+    pub fn undo(&mut self) {
+        self.commit_typing();
+        let position = self.contents.undo();
+        self.show_history_change(position);
+    }
+
     pub fn redo(&mut self) {
-        self.contents.redo();
+        self.commit_typing();
+        let position = self.contents.redo();
+        self.show_history_change(position);
+    }
+
+    pub fn earlier(&mut self) {
+        self.commit_typing();
+        let position = self.contents.earlier();
+        self.show_history_change(position);
+    }
+
+    pub fn later(&mut self) {
+        self.commit_typing();
+        let position = self.contents.later();
+        self.show_history_change(position);
+    }
+
+    pub fn switch_branch(&mut self) {
+        self.commit_typing();
+        let position = self.contents.switch_branch();
+        self.show_history_change(position);
+    }
+
+    /// Asks how many minutes back to restore the text to.
+    pub fn prompt_time_travel(&mut self) {
+        self.commit_typing();
+        execute!(self.stdout, SavePosition).unwrap();
+        self.custom_prompt = Some(Prompt::TimeTravel);
+        self.custom_name = Some(String::from(""));
+        self.render_custom_prompt();
+    }
+
+    fn time_travel(&mut self, minutes: u64) {
+        let time = SystemTime::now() - Duration::from_secs(minutes * 60);
+        let position = self.contents.at_time(time);
+        self.show_history_change(position);
+    }
+
+    /// Asks which encoding to write the file in from now on.
+    pub fn prompt_encoding(&mut self) {
+        self.commit_typing();
+        execute!(self.stdout, SavePosition).unwrap();
+        self.custom_prompt = Some(Prompt::Encoding);
+        self.custom_name = Some(String::from(""));
+        self.render_custom_prompt();
+    }
+
+    fn convert_encoding(&mut self, label: &str) {
+        match FileEncoding::from_label(label) {
+            Some(encoding) => self.encoding = encoding,
+            None => self.message = Some(format!("Unknown encoding: {}", label)),
+        }
+        self.render_contents();
+    }
+
+    /// Switches between wrapping long lines at the window width and
+    /// scrolling sideways through them.
+    pub fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+        self.column_offset = 0;
+        self.column_pos = None;
+        self.render_contents();
+        self.place_cursor();
+    }
+
+    /// Picks the line ending every line gets on the next save: the most
+    /// common one for a file with mixed endings, then the other one.
+    pub fn toggle_line_ending(&mut self) {
+        if self.convert_endings || !self.mixed_endings {
+            self.line_ending = self.line_ending.other();
+        }
+        self.convert_endings = true;
+        self.render_bottom_bar();
+    }
+
+    /// Moves the cursor to the start or end of the text.
+    pub fn move_to_edge(&mut self, direction: Direction) {
+        if self.custom_prompt.is_some() {
+            return;
+        }
+        self.commit_typing();
+        let offset = match direction {
+            Direction::Up | Direction::Left => 0,
+            Direction::Down | Direction::Right => self.contents.len(),
+        };
+        self.jump_to(offset);
+    }
+
+    /// Moves the cursor to the previous start or next end of a word.
+    pub fn move_word(&mut self, direction: Direction) {
+        if self.custom_prompt.is_some() {
+            return;
+        }
+        self.commit_typing();
+        if let Some(position) = self.get_position() {
+            let offset = match direction {
+                Direction::Up | Direction::Left => self.contents.prev_word_boundary(position),
+                Direction::Down | Direction::Right => self.contents.next_word_boundary(position),
+            };
+            self.jump_to(offset);
+        }
+    }
+
+    /// Moves the cursor to a char offset anywhere in the text.
+    fn jump_to(&mut self, offset: usize) {
+        self.anchor = None;
+        self.move_to_offset(offset);
+        self.column_pos = None;
+        self.render_contents();
+    }
+
+    /// Deletes back to the start of the word before the cursor.
+    pub fn delete_word(&mut self) {
+        if self.custom_prompt.is_some() {
+            return;
+        }
+        self.commit_typing();
+        if self.replace_selection("") {
+            self.render_contents();
+        } else if let Some(position) = self.get_position() {
+            let start = self.contents.prev_word_boundary(position);
+            let deleted = self.contents.delete_range(start..position);
+            if self.edited(deleted) {
+                self.move_to_offset(start);
+            }
+            self.column_pos = None;
+            self.render_contents();
+        }
+    }
+
+    fn show_history_change(&mut self, position: Option<usize>) {
+        self.anchor = None;
+        if let Some(position) = position {
+            self.move_to_offset(position);
+            self.column_pos = None;
+        }
+        self.render_contents();
+    }
+
+    /// Takes the current line back one indent level, keeping the cursor
+    /// on the same text.
+    fn dedent(&mut self) {
+        let line = self.current_line();
+        let text = self.contents.line(line);
+        let removed = self.indent.dedent(&text, self.tab_stop);
+        let Some(start) = self.contents.line_start(line) else {
+            return;
+        };
+        if removed == 0 {
+            return;
+        }
+        let deleted = self.contents.delete_range(start..start + removed);
+        if self.edited(deleted) {
+            let column = self.cursor_pos.x.saturating_sub(removed);
+            self.move_to_offset(start + column);
+        }
+        self.column_pos = None;
         self.render_contents();
     }
 
     fn write(&mut self, char: char) {
+        self.write_str(char.encode_utf8(&mut [0; 4]));
+    }
+
+    fn write_str(&mut self, text: &str) {
+        if self.replace_selection(text) {
+            return;
+        }
         if let Some(position) = self.get_position() {
-            execute!(self.stdout, MoveRight(1)).unwrap();
-            self.cursor_pos.x += 1;
-            if true {
-                self.contents.insert(char, position);
+            // INFO: group a typed word into one undo step
+            if !self.typing {
+                self.contents.begin_transaction();
+                self.typing = true;
             }
-        } else {
+            let inserted = self.contents.insert_str(text, position);
+            if self.edited(inserted) {
+                self.move_to_offset(position + text.chars().count());
+            }
+            if !text.chars().all(char::is_alphanumeric) {
+                self.commit_typing();
+            }
+        }
+    }
+
+    /// Shows why an edit was refused until the next redraw. Returns
+    /// whether it went through.
+    fn edited(&mut self, result: Result<(), EditError>) -> bool {
+        if let Err(error) = &result {
+            self.message = Some(format!("Not edited: {}", error));
+        }
+        result.is_ok()
+    }
+
+    fn commit_typing(&mut self) {
+        if self.typing {
+            self.contents.commit_transaction();
+            self.typing = false;
+        }
+    }
+
+    pub fn paste(&mut self, text: String) {
+        if self.custom_prompt.is_some() {
             return;
         }
+        self.commit_typing();
+        if let Some(position) = self.get_position() {
+            let text = self.line_ending.normalize(&text);
+            if !self.replace_selection(&text) {
+                let inserted = self.contents.insert_str(&text, position);
+                if self.edited(inserted) {
+                    self.move_to_offset(position + text.chars().count());
+                }
+            }
+            self.column_pos = None;
+            self.render_contents();
+        }
     }
 
     pub fn close(&mut self) {
         let _ = disable_raw_mode();
+        let _ = execute!(self.stdout, DisableBracketedPaste);
         execute!(
             self.stdout,
             LeaveAlternateScreen,
@@ -360,19 +1103,79 @@ impl Editor {
     }
 
     pub fn write_to_file(&mut self) {
-        let file_contents = self.contents.read();
+        self.commit_typing();
+        // INFO: a file that stopped loading is incomplete, saving would cut it
+        if let Err(error) = self.contents.finish_loading() {
+            self.reopen(error);
+            self.render_contents();
+            self.place_cursor();
+            return;
+        }
+        if self.file_path.is_some() && self.convert_endings {
+            self.anchor = None;
+            line_ending::convert(self.contents.as_mut(), self.line_ending);
+            (self.convert_endings, self.mixed_endings) = (false, false);
+            self.render_contents();
+        }
         if let Some(path) = &self.file_path {
-            let _ = fs::write(path, file_contents);
+            match self.save(path) {
+                Ok(()) => {
+                    let _ = self.contents.save_history(&undo_path(path));
+                }
+                Err(error) => {
+                    self.message = Some(format!("Not saved: {}", error));
+                    self.render_bottom_bar();
+                }
+            }
         } else {
             execute!(self.stdout, SavePosition).unwrap();
-            self.custom_prompt = true;
+            self.custom_prompt = Some(Prompt::FileName);
             self.custom_name = Some(String::from(""));
             self.render_custom_prompt();
         }
     }
 
-    pub fn write_pieces(&self) {
-        let pieces = self.contents.get_pieces();
-        let _ = fs::write("debug.json", pieces);
+    /// Streams the text to a file in its encoding without building it as
+    /// one `String`. The text goes to a temporary file that then replaces
+    /// the old one, which a large file's text may still be mapped from.
+    fn save(&self, path: &str) -> io::Result<()> {
+        // INFO: replace the file a symlink points to, not the symlink; a new
+        // file has nothing to resolve
+        let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        let temp_path = sibling_path(&path, "tmp");
+        let written = File::create(&temp_path).and_then(|file| {
+            let mut file = BufWriter::new(file);
+            let chunks = self.contents.chunks(0..self.contents.len());
+            self.encoding.write(&mut file, chunks)?;
+            file.flush()
+        });
+        if let Err(error) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(error);
+        }
+        if let Ok(metadata) = fs::metadata(&path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+        fs::rename(&temp_path, path)
+    }
+
+    /// Writes the internal state of the text next to the file, or to the
+    /// temporary directory for a new file, to attach to a bug report.
+    pub fn write_dump(&mut self) {
+        self.commit_typing();
+        let Some(dump) = self.contents.debug_dump() else {
+            self.message = Some(String::from("Nothing to dump"));
+            self.render_bottom_bar();
+            return;
+        };
+        let path = match &self.file_path {
+            Some(file) => sibling_path(Path::new(file), "dump.json"),
+            None => env::temp_dir().join("edito.rs.dump.json"),
+        };
+        self.message = Some(match fs::write(&path, dump) {
+            Ok(()) => format!("Dumped to {}", path.display()),
+            Err(error) => format!("Not dumped: {}", error),
+        });
+        self.render_bottom_bar();
     }
 }
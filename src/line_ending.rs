@@ -0,0 +1,92 @@
+use crate::piece_table::Edit;
+use crate::text_buffer::TextBuffer;
+use std::fmt;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    pub fn other(&self) -> LineEnding {
+        match self {
+            LineEnding::Lf => LineEnding::Crlf,
+            LineEnding::Crlf => LineEnding::Lf,
+        }
+    }
+
+    /// Rewrites all line breaks of some text to this ending, treating
+    /// a lone `\r` as a line break too.
+    pub fn normalize(&self, text: &str) -> String {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        match self {
+            LineEnding::Lf => text,
+            LineEnding::Crlf => text.replace('\n', "\r\n"),
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineEnding::Lf => write!(f, "LF"),
+            LineEnding::Crlf => write!(f, "CRLF"),
+        }
+    }
+}
+
+/// Offsets of the newlines in the text, with whether each follows a `\r`.
+fn newlines(text: &dyn TextBuffer) -> Vec<(usize, bool)> {
+    let mut newlines = Vec::new();
+    let mut previous = None;
+    let chars = text.chunks(0..text.len()).flat_map(str::chars);
+    for (offset, c) in chars.enumerate() {
+        if c == '\n' {
+            newlines.push((offset, previous == Some('\r')));
+        }
+        previous = Some(c);
+    }
+    newlines
+}
+
+/// The line ending used by most lines, and whether some lines use the
+/// other one. Text without line breaks counts as LF.
+pub fn detect(text: &dyn TextBuffer) -> (LineEnding, bool) {
+    let newlines = newlines(text);
+    let crlf = newlines.iter().filter(|&&(_, crlf)| crlf).count();
+    let lf = newlines.len() - crlf;
+    let ending = if crlf > lf {
+        LineEnding::Crlf
+    } else {
+        LineEnding::Lf
+    };
+    (ending, crlf > 0 && lf > 0)
+}
+
+/// Changes every line break to `ending` as one undo step.
+pub fn convert(text: &mut dyn TextBuffer, ending: LineEnding) {
+    let edits = newlines(text)
+        .into_iter()
+        .filter_map(|(offset, crlf)| match (ending, crlf) {
+            (LineEnding::Lf, true) => Some(Edit {
+                range: offset - 1..offset,
+                text: String::new(),
+            }),
+            (LineEnding::Crlf, false) => Some(Edit {
+                range: offset..offset,
+                text: String::from('\r'),
+            }),
+            _ => None,
+        })
+        .collect();
+    text.apply_edits(edits)
+        .expect("Newlines should lie within the text");
+}
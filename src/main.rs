@@ -3,10 +3,11 @@ use crossterm::{
     execute,
     terminal::LeaveAlternateScreen,
 };
-use editors::Editor;
+use editors::{Direction, Editor};
 use std::env;
 use std::io::stdout;
 use std::panic::set_hook;
+use std::time::Duration;
 
 fn main() {
     set_hook(Box::new(|e| {
@@ -18,6 +19,13 @@ fn main() {
     let mut editor = Editor::build(env::args()).unwrap();
     editor.init();
     loop {
+        // INFO: keep showing a large file as it loads, between key presses
+        if editor.is_loading() {
+            editor.poll_loading();
+            if !crossterm::event::poll(Duration::from_millis(50)).unwrap_or(false) {
+                continue;
+            }
+        }
         match crossterm::event::read() {
             Ok(Event::Key(event)) => match event {
                 KeyEvent {
@@ -28,9 +36,22 @@ fn main() {
                 } => match code {
                     KeyCode::Char('s') => editor.write_to_file(),
                     KeyCode::Char('c') => break,
-                    KeyCode::Char('p') => editor.write_pieces(),
+                    KeyCode::Char('p') => editor.write_dump(),
                     KeyCode::Char('z') => editor.undo(),
                     KeyCode::Char('r') => editor.redo(),
+                    KeyCode::Char('e') => editor.earlier(),
+                    KeyCode::Char('l') => editor.later(),
+                    KeyCode::Char('b') => editor.switch_branch(),
+                    KeyCode::Char('t') => editor.prompt_time_travel(),
+                    KeyCode::Char('n') => editor.prompt_encoding(),
+                    KeyCode::Char('k') => editor.toggle_line_ending(),
+                    KeyCode::Char('w') => editor.toggle_wrap(),
+                    KeyCode::Home => editor.move_to_edge(Direction::Up),
+                    KeyCode::End => editor.move_to_edge(Direction::Down),
+                    KeyCode::Left => editor.move_word(Direction::Left),
+                    KeyCode::Right => editor.move_word(Direction::Right),
+                    // INFO: many terminals send Ctrl+Backspace as Ctrl+H
+                    KeyCode::Backspace | KeyCode::Char('h') => editor.delete_word(),
                     _ => continue,
                 },
                 KeyEvent {
@@ -38,22 +59,34 @@ fn main() {
                     code,
                     kind: _,
                     state: _,
-                } => match code {
-                    keycode => editor.handle_key_input(keycode),
-                },
+                } => editor.handle_key_input(code),
+                KeyEvent {
+                    modifiers: KeyModifiers::SHIFT,
+                    code: KeyCode::Char(ch),
+                    kind: _,
+                    state: _,
+                } => editor.handle_key_input(KeyCode::Char(ch.to_uppercase().next().unwrap())),
                 KeyEvent {
                     modifiers: KeyModifiers::SHIFT,
                     code,
                     kind: _,
                     state: _,
-                } => match code {
-                    KeyCode::Char(ch) => {
-                        editor.handle_key_input(KeyCode::Char(ch.to_uppercase().next().unwrap()))
-                    }
+                } => editor.select(code),
+                KeyEvent {
+                    modifiers,
+                    code,
+                    kind: _,
+                    state: _,
+                } if modifiers == KeyModifiers::CONTROL | KeyModifiers::SHIFT => match code {
+                    KeyCode::Home => editor.select_to_edge(Direction::Up),
+                    KeyCode::End => editor.select_to_edge(Direction::Down),
+                    KeyCode::Left => editor.select_word(Direction::Left),
+                    KeyCode::Right => editor.select_word(Direction::Right),
                     _ => continue,
                 },
                 _ => continue,
             },
+            Ok(Event::Paste(text)) => editor.paste(text),
             Ok(Event::Resize(_, _)) => editor.render_contents(),
             Ok(_) => continue,
             Err(_) => continue,
@@ -1,209 +1,610 @@
-use core::fmt;
-use std::usize;
+use anchor::Anchors;
+use chunk::Added;
+use compact::Compaction;
+pub use anchor::{Anchor, Gravity};
+pub use batch::Edit;
+pub use change::Change;
+pub(crate) use error::check_range;
+pub use error::{Corruption, EditError};
+use history::{History, Step};
+pub use iter::{Chars, Chunks, Lines, Slice};
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::ops::{Deref, Range};
+use std::str;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tree::PieceTree;
 
+mod anchor;
+mod batch;
+mod change;
+mod chunk;
+mod compact;
+mod dump;
+mod error;
+mod grapheme;
+mod history;
+mod iter;
+mod loader;
+mod tree;
+mod undo_file;
+mod validate;
+
+/// Editable text with undo history. All reading goes through the
+/// `Snapshot` of the current text it dereferences to.
 pub struct PieceTable {
-    original_buf: Buffer,
-    add_buf: Buffer,
-    pieces: Vec<Piece>,
-    pieces_history: Vec<Vec<Piece>>,
-    history_pos: usize,
+    text: Snapshot,
+    history: History,
+    anchors: Anchors,
+    changes: Option<Vec<Change>>,
+    compaction: Compaction,
+    loader: Option<Receiver<Result<Buffer, usize>>>,
+    /// Byte offset at which a mapped file turned out not to be UTF-8,
+    /// which ended its loading.
+    not_utf8: Option<usize>,
 }
 
+/// Read-only view of the text of a `PieceTable` at one point in time. It
+/// shares the buffers and chunks of the table and can be sent to other
+/// threads; edits made to the table after it was taken do not show in it.
+#[derive(Clone)]
+pub struct Snapshot {
+    original: Arc<Vec<Arc<Buffer>>>,
+    added: Added,
+    pieces: PieceTree,
+}
+
+// INFO: snapshots are meant for background threads
+const _: fn() = || {
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<Snapshot>();
+};
+
+/// Bytes between the char count checkpoints kept by a buffer.
+const MARK_SPACING: usize = 1024;
+
 struct Buffer {
-    contents: String,
+    contents: Contents,
+    index: Index,
+}
+
+/// Text of a buffer: either owned, or a UTF-8 range of a read-only file
+/// mapping.
+enum Contents {
+    Owned(String),
+    Mapped { map: Arc<Mmap>, range: Range<usize> },
+}
+
+/// Char count, newline positions and char count checkpoints of the text
+/// of a buffer, as pairs of byte offset and chars before it.
+#[derive(Default)]
+struct Index {
+    chars: usize,
+    newlines: Vec<usize>,
+    marks: Vec<(usize, usize)>,
 }
 
+/// A run of text in one of the buffers. `start` and `length` are byte
+/// offsets into the buffer, `chars` and `newlines` are cached metrics.
 #[derive(Clone)]
 struct Piece {
     source: Source,
     start: usize,
     length: usize,
+    chars: usize,
+    newlines: usize,
 }
 
-#[derive(Copy, Clone, PartialEq)]
+/// Buffer a piece points into: the original text or the text added by
+/// edits, each kept in a list of buffers that only grows at the end.
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 enum Source {
-    Original,
-    Added,
+    Original(usize),
+    Added(usize),
 }
 
-impl fmt::Display for Source {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Source::Original => write!(f, "Original"),
-            Source::Added => write!(f, "Added"),
+impl Piece {
+    /// Joins two pieces that are contiguous in the same buffer.
+    fn merged(&self, next: &Piece) -> Option<Piece> {
+        if self.source == next.source && self.start + self.length == next.start {
+            Some(Piece {
+                source: self.source,
+                start: self.start,
+                length: self.length + next.length,
+                chars: self.chars + next.chars,
+                newlines: self.newlines + next.newlines,
+            })
+        } else {
+            None
         }
     }
 }
 
-impl PieceTable {
-    pub fn new(contents: String) -> PieceTable {
-        let original_buf = Buffer { contents };
-        let pieces = vec![Piece {
-            source: Source::Original,
-            start: 0,
-            length: original_buf.contents.chars().count(),
-        }];
-        PieceTable {
-            original_buf,
-            add_buf: Buffer {
-                contents: String::from(""),
+impl Index {
+    /// Counts the chars and newlines of text starting at byte `start`.
+    fn scan(&mut self, text: &str, start: usize) {
+        for (i, c) in text.char_indices() {
+            // INFO: checkpoint the char count about every MARK_SPACING bytes
+            let due = self.marks.last().map(|&(byte, _)| byte + MARK_SPACING);
+            if due.is_none_or(|due| start + i >= due) {
+                self.marks.push((start + i, self.chars));
+            }
+            if c == '\n' {
+                self.newlines.push(start + i);
+            }
+            self.chars += 1;
+        }
+    }
+}
+
+impl Buffer {
+    fn new(contents: String) -> Buffer {
+        let mut index = Index::default();
+        index.scan(&contents, 0);
+        Buffer {
+            contents: Contents::Owned(contents),
+            index,
+        }
+    }
+
+    /// Buffer over a range of a file mapping, or the byte offset at which
+    /// the range stops being UTF-8.
+    fn mapped(map: Arc<Mmap>, range: Range<usize>) -> Result<Buffer, usize> {
+        let text = str::from_utf8(&map[range.clone()])
+            .map_err(|error| range.start + error.valid_up_to())?;
+        let mut index = Index::default();
+        index.scan(text, 0);
+        Ok(Buffer {
+            contents: Contents::Mapped { map, range },
+            index,
+        })
+    }
+
+    /// The text of the buffer.
+    fn text(&self) -> &str {
+        match &self.contents {
+            Contents::Owned(text) => text,
+            // SAFETY: `mapped` checked the range to be UTF-8
+            Contents::Mapped { map, range } => unsafe {
+                str::from_utf8_unchecked(&map[range.clone()])
             },
-            pieces: pieces.clone(),
-            pieces_history: vec![pieces],
-            history_pos: 0,
         }
     }
 
-    fn store(&mut self) {
-        self.history_pos = self.history_pos + 1;
-        self.pieces_history.resize(self.history_pos, Vec::new());
-        self.pieces_history.push(self.pieces.clone());
+    /// The text of the buffer along with its index.
+    fn indexed(&self) -> Text<'_> {
+        Text {
+            text: self.text(),
+            index: Some(&self.index),
+        }
     }
 
-    pub fn undo(&mut self) {
-        if self.history_pos > 0 {
-            self.history_pos = self.history_pos - 1;
-            self.pieces = self.pieces_history[self.history_pos].clone();
+    /// A piece covering the whole buffer.
+    fn piece(&self, source: Source) -> Piece {
+        Piece {
+            source,
+            start: 0,
+            length: self.text().len(),
+            chars: self.index.chars,
+            newlines: self.index.newlines.len(),
         }
     }
+}
+
+/// Number of newlines in a text.
+fn count_newlines(text: &str) -> usize {
+    text.bytes().filter(|&byte| byte == b'\n').count()
+}
+
+/// The text a piece points into. Buffers come with their index; chunks of
+/// added text have none and are scanned, which their size keeps cheap.
+#[derive(Copy, Clone)]
+struct Text<'a> {
+    text: &'a str,
+    index: Option<&'a Index>,
+}
+
+impl<'a> Text<'a> {
+    /// Borrows the text between two byte offsets.
+    fn slice(&self, range: Range<usize>) -> &'a str {
+        &self.text[range]
+    }
 
-    pub fn redo(&mut self) {
-        if self.history_pos < self.pieces_history.len() - 1 {
-            self.history_pos = self.history_pos + 1;
-            self.pieces = self.pieces_history[self.history_pos].clone();
+    /// Number of chars before a byte offset, using the checkpoints of an
+    /// index.
+    fn byte_to_char(&self, index: &Index, byte: usize) -> usize {
+        let (mark_byte, mark_chars) = match index.marks.partition_point(|&(b, _)| b <= byte) {
+            0 => (0, 0),
+            i => index.marks[i - 1],
+        };
+        mark_chars + self.text[mark_byte..byte].chars().count()
+    }
+
+    /// Byte offset of a char index, using the checkpoints of an index.
+    fn char_to_byte(&self, index: &Index, char: usize) -> usize {
+        let (mark_byte, mark_chars) = match index.marks.partition_point(|&(_, c)| c <= char) {
+            0 => (0, 0),
+            i => index.marks[i - 1],
+        };
+        self.text[mark_byte..]
+            .char_indices()
+            .nth(char - mark_chars)
+            .map_or(self.text.len(), |(i, _)| mark_byte + i)
+    }
+
+    /// Byte offset `count` chars after the byte offset `start`.
+    fn advance(&self, start: usize, count: usize) -> usize {
+        match self.index {
+            Some(index) if count >= MARK_SPACING => {
+                self.char_to_byte(index, self.byte_to_char(index, start) + count)
+            }
+            _ => self.text[start..]
+                .char_indices()
+                .nth(count)
+                .map_or(self.text.len(), |(i, _)| start + i),
         }
     }
 
-    pub fn insert(&mut self, insert_char: char, position: usize) {
-        // TODO: Check for previous insert
-        let start = self.add_buf.contents.chars().count();
-        let mut insert_index: Option<usize> = None;
-        let mut text_position = 0;
-        let mut offset = 0;
-        let add_buf_length = self.add_buf.contents.chars().count();
-        for (i, piece) in self.pieces.iter().enumerate() {
-            if piece.source == Source::Added && piece.start + piece.length == add_buf_length {
-                // TODO: append if new piece at insert_index + 1
+    /// Number of chars between two byte offsets.
+    fn chars_in(&self, range: Range<usize>) -> usize {
+        match self.index {
+            Some(index) if range.len() >= MARK_SPACING => {
+                self.byte_to_char(index, range.end) - self.byte_to_char(index, range.start)
             }
-            if text_position == position {
-                insert_index = Some(i);
-            } else if text_position < position && text_position + piece.length > position {
-                insert_index = Some(i + 1);
-                offset = position - text_position;
+            _ => self.text[range].chars().count(),
+        }
+    }
+
+    /// Counts the newlines between two byte offsets, in O(log n) with an
+    /// index.
+    fn newlines_in(&self, range: Range<usize>) -> usize {
+        match self.index {
+            Some(index) => {
+                let newlines = &index.newlines;
+                newlines.partition_point(|&i| i < range.end)
+                    - newlines.partition_point(|&i| i < range.start)
             }
-            text_position += piece.length;
+            None => count_newlines(&self.text[range]),
         }
+    }
 
-        if position > text_position {
-            panic!("String insert out of bounds!");
+    /// Byte offset of the newline `n` newlines after the byte offset
+    /// `start`, which must exist.
+    fn nth_newline(&self, start: usize, n: usize) -> usize {
+        match self.index {
+            Some(index) => index.newlines[index.newlines.partition_point(|&i| i < start) + n],
+            None => self.text.as_bytes()[start..]
+                .iter()
+                .enumerate()
+                .filter(|&(_, &byte)| byte == b'\n')
+                .nth(n)
+                .map(|(i, _)| start + i)
+                .expect("Piece should hold the newline"),
         }
+    }
 
-        let new_piece = Piece {
-            source: Source::Added,
-            start,
-            length: 1,
-        };
+    /// Cuts a piece of this text in two after `chars` chars.
+    fn split_piece(&self, piece: &Piece, chars: usize) -> (Piece, Piece) {
+        let middle = self.advance(piece.start, chars);
+        let newlines = self.newlines_in(piece.start..middle);
+        (
+            Piece {
+                source: piece.source,
+                start: piece.start,
+                length: middle - piece.start,
+                chars,
+                newlines,
+            },
+            Piece {
+                source: piece.source,
+                start: middle,
+                length: piece.start + piece.length - middle,
+                chars: piece.chars - chars,
+                newlines: piece.newlines - newlines,
+            },
+        )
+    }
+}
+
+/// Looks up the text of a source among the buffers and chunks of a table.
+fn buffer<'a>(original: &'a [Arc<Buffer>], added: &'a Added, source: Source) -> Text<'a> {
+    match source {
+        Source::Original(i) => original[i].indexed(),
+        Source::Added(i) => Text {
+            text: added.text(i),
+            index: None,
+        },
+    }
+}
+
+/// Returns the piece splitter used by the piece tree, which needs the
+/// buffers to find the byte offset of the cut and recount newlines.
+fn splitter<'a>(
+    original: &'a [Arc<Buffer>],
+    added: &'a Added,
+) -> impl Fn(&Piece, usize) -> (Piece, Piece) + 'a {
+    move |piece, chars| buffer(original, added, piece.source).split_piece(piece, chars)
+}
+
+impl PieceTable {
+    pub fn new(contents: String) -> PieceTable {
+        let original = Buffer::new(contents);
+        let mut pieces = PieceTree::new();
+        pieces.push(original.piece(Source::Original(0)));
+        PieceTable {
+            text: Snapshot {
+                original: Arc::new(vec![Arc::new(original)]),
+                added: Added::default(),
+                pieces,
+            },
+            history: History::default(),
+            anchors: Anchors::default(),
+            changes: None,
+            compaction: Compaction::default(),
+            loader: None,
+            not_utf8: None,
+        }
+    }
+
+    /// Read-only copy of the current text, taken in O(1) by sharing the
+    /// buffers with the table.
+    pub fn snapshot(&self) -> Snapshot {
+        self.text.clone()
+    }
+
+    /// Groups all following edits into one undo step until the matching
+    /// `commit_transaction`. Transactions may be nested.
+    pub fn begin_transaction(&mut self) {
+        self.history.begin();
+    }
+
+    pub fn commit_transaction(&mut self) {
+        self.history.commit();
+        self.count_dropped();
+    }
 
-        if offset > 0 {
-            let i = insert_index.unwrap();
-            let split_off_piece = {
-                let split_piece = &self.pieces[i - 1];
+    /// Reverts the last undo step and returns the offset it started at.
+    pub fn undo(&mut self) -> Option<usize> {
+        let steps = self.history.undo();
+        self.apply_steps(steps)
+    }
+
+    /// Reapplies the last undone step and returns the offset it ended at.
+    pub fn redo(&mut self) -> Option<usize> {
+        let steps = self.history.redo();
+        self.apply_steps(steps)
+    }
+
+    /// Moves to the previous state in time, across undo branches.
+    pub fn earlier(&mut self) -> Option<usize> {
+        let steps = self.history.earlier();
+        self.apply_steps(steps)
+    }
+
+    /// Moves to the next state in time, across undo branches.
+    pub fn later(&mut self) -> Option<usize> {
+        let steps = self.history.later();
+        self.apply_steps(steps)
+    }
+
+    /// Moves to the next sibling branch of the current undo state.
+    pub fn switch_branch(&mut self) -> Option<usize> {
+        let steps = self.history.switch_branch();
+        self.apply_steps(steps)
+    }
 
-                Piece {
-                    source: split_piece.source,
-                    start: split_piece.start + offset,
-                    length: split_piece.length - offset,
+    /// Moves to the state the text was in at `time`.
+    pub fn at_time(&mut self, time: SystemTime) -> Option<usize> {
+        let steps = self.history.at_time(time);
+        self.apply_steps(steps)
+    }
+
+    /// Applies undo tree steps and returns the offset of the last change.
+    fn apply_steps(&mut self, steps: Vec<Step>) -> Option<usize> {
+        let mut position = None;
+        for step in steps {
+            match step {
+                Step::Undo(edits) => {
+                    for edit in edits.iter().rev() {
+                        let range = edit.position..edit.position + edit.inserted.len();
+                        self.splice(range, edit.removed.clone());
+                    }
+                    position = edits.first().map(|edit| edit.position);
+                }
+                Step::Redo(edits) => {
+                    for edit in edits.iter() {
+                        let range = edit.position..edit.position + edit.removed.len();
+                        self.splice(range, edit.inserted.clone());
+                    }
+                    position = edits.last().map(|edit| edit.position + edit.inserted.len());
                 }
-            };
-            self.pieces[i - 1].length = offset;
-            self.pieces.insert(i, new_piece);
-            self.pieces.insert(i + 1, split_off_piece);
-        } else if let Some(i) = insert_index {
-            self.pieces.insert(i, new_piece);
-        } else {
-            self.pieces.push(new_piece);
-        }
-
-        self.add_buf.contents.push(insert_char);
-
-        self.store();
-    }
-
-    pub fn delete(&mut self, position: usize) {
-        let mut cursor_pos = 0;
-        for (i, piece) in self.pieces.iter_mut().enumerate() {
-            if cursor_pos + piece.length - 1 < position {
-                cursor_pos += piece.length;
-            } else if piece.length == 1 {
-                // INFO: Full: delete piece
-                self.pieces.remove(i);
-                break;
-            } else if cursor_pos + piece.length - 1 == position {
-                // INFO: End: shorten piece by one
-                piece.length -= 1;
-                break;
-            } else if cursor_pos == position {
-                // INFO: Start: shorten and shift piece by one
-                piece.start += 1;
-                piece.length -= 1;
-                break;
-            } else {
-                // INFO: Middle: split piece in two
-                let new_length = position - cursor_pos;
-                let new_piece = Piece {
-                    source: piece.source,
-                    start: piece.start + new_length + 1,
-                    length: piece.length - new_length - 1,
-                };
-                piece.length = new_length;
-                self.pieces.insert(i + 1, new_piece);
-                break;
             }
         }
-        self.store();
+        position
     }
 
+    pub fn insert(&mut self, insert_char: char, position: usize) -> Result<(), EditError> {
+        self.insert_str(insert_char.encode_utf8(&mut [0; 4]), position)
+    }
+
+    pub fn insert_str(&mut self, text: &str, position: usize) -> Result<(), EditError> {
+        self.check_loaded()?;
+        check_range(self.len(), position..position)?;
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let inserted = self.push_added(text);
+        self.replace(position..position, inserted);
+        Ok(())
+    }
+
+    /// Appends text to the added chunks and returns pieces covering it.
+    /// Snapshots sharing the last chunk do not see the text appended to it.
+    fn push_added(&mut self, text: &str) -> PieceTree {
+        self.text.added.push_str(text)
+    }
+
+    /// Deletes the grapheme cluster starting at `position`, if any.
+    pub fn delete(&mut self, position: usize) -> Result<(), EditError> {
+        check_range(self.len(), position..position)?;
+        let end = self.next_grapheme_boundary(position);
+        self.delete_range(position..end)
+    }
+
+    pub fn delete_range(&mut self, range: Range<usize>) -> Result<(), EditError> {
+        self.check_loaded()?;
+        check_range(self.len(), range.clone())?;
+        if !range.is_empty() {
+            self.replace(range, PieceTree::new());
+        }
+        Ok(())
+    }
+
+    /// Replaces a range with new pieces and records it in the undo log.
+    fn replace(&mut self, range: Range<usize>, inserted: PieceTree) {
+        let position = range.start;
+        let removed = self.splice(range, inserted.clone());
+        self.history.record(history::Edit {
+            position,
+            removed,
+            inserted,
+        });
+        self.count_dropped();
+    }
+
+    /// Replaces a range with new pieces and returns the removed ones.
+    fn splice(&mut self, range: Range<usize>, inserted: PieceTree) -> PieceTree {
+        self.anchors.adjust(range.clone(), inserted.len());
+        self.queue_change(range.clone(), &inserted);
+        self.text.splice(range, inserted)
+    }
+}
+
+impl Deref for PieceTable {
+    type Target = Snapshot;
+
+    fn deref(&self) -> &Snapshot {
+        &self.text
+    }
+}
+
+impl Snapshot {
     pub fn read(&self) -> String {
-        let mut output = String::from("");
-        let original_chars: Vec<char> = self.original_buf.contents.chars().collect();
-        let added_chars: Vec<char> = self.add_buf.contents.chars().collect();
-
-        for piece in self.pieces.iter() {
-            let string: String = match &piece.source {
-                Source::Original => original_chars[piece.start..(piece.start + piece.length)]
-                    .iter()
-                    .collect(),
-                Source::Added => added_chars[piece.start..(piece.start + piece.length)]
-                    .iter()
-                    .collect(),
-            };
-            output += &string;
-        }
-        output
-    }
-
-    pub fn get_line_length(&self, line_index: u16) -> usize {
-        let mut line_length = 0;
-        let text = self.read();
-        if let Some(line) = text.lines().nth(line_index.into()) {
-            line_length = line.chars().count();
-        }
-        return line_length;
-    }
-
-    pub fn get_pieces(&self) -> String {
-        let mut json = String::from("[");
-        for piece in &self.pieces {
-            json += &format!(
-                "{{\n  \"source\": \"{}\",\n  \"start\": {},\n  \"length\": {}\n}},",
-                piece.source, piece.start, piece.length
-            )
-            .to_string();
-        }
-        if self.pieces.len() > 0 {
-            json.pop();
-        }
-        json += "]";
-        return json;
+        self.chunks().collect()
+    }
+
+    /// Swaps the pieces of a range for new ones, leaving anchors and queued
+    /// changes to the caller, and returns the removed pieces.
+    fn splice(&mut self, range: Range<usize>, inserted: PieceTree) -> PieceTree {
+        let split = splitter(&self.original, &self.added);
+        let tail = self.pieces.split_off(range.end, &split);
+        let removed = self.pieces.split_off(range.start, &split);
+        self.pieces.append(inserted);
+        self.pieces.append(tail);
+        debug_assert_eq!(self.validate(), Ok(()));
+        removed
+    }
+
+    /// Number of chars in the text.
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    /// Iterates over the whole text as one `&str` per piece.
+    pub fn chunks(&self) -> Chunks<'_> {
+        self.slice(0..self.len()).chunks()
+    }
+
+    /// Borrows a char range of the text, clamped to its length.
+    pub fn slice(&self, range: Range<usize>) -> Slice<'_> {
+        Slice::new(self, range)
+    }
+
+    /// Returns a cursor over the chars starting at a char offset.
+    pub fn chars_at(&self, offset: usize) -> Chars<'_> {
+        Chars::new(self, offset)
+    }
+
+    /// Iterates over the lines starting at `line`, excluding line breaks.
+    pub fn lines_at(&self, line: usize) -> Lines<'_> {
+        Lines::new(self, line)
+    }
+
+    fn buffer(&self, source: Source) -> Text<'_> {
+        buffer(&self.original, &self.added, source)
+    }
+
+    /// Number of lines, counting the (possibly empty) line after the last newline.
+    pub fn line_count(&self) -> usize {
+        self.pieces.newlines() + 1
+    }
+
+    /// Char offset of the first character of a line.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            return Some(0);
+        }
+        let found = self.pieces.find_newline(line)?;
+        let piece = found.piece;
+        let buffer = self.buffer(piece.source);
+        let newline = buffer.nth_newline(piece.start, line - found.newlines - 1);
+        Some(found.chars + buffer.chars_in(piece.start..newline) + 1)
+    }
+
+    /// Number of chars on a line, excluding the line break, be it `\n` or
+    /// `\r\n`.
+    pub fn line_length(&self, line: usize) -> usize {
+        let Some(start) = self.line_start(line) else {
+            return 0;
+        };
+        let Some(next) = self.line_start(line + 1) else {
+            return self.pieces.len() - start;
+        };
+        let mut end = next - 1;
+        if end > start && self.chars_at(end).prev() == Some('\r') {
+            end -= 1;
+        }
+        end - start
+    }
+
+    pub fn offset_to_line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = cmp::min(offset, self.pieces.len());
+        let line = match self.pieces.find(offset) {
+            Some(found) => {
+                let piece = found.piece;
+                let buffer = self.buffer(piece.source);
+                let end = buffer.advance(piece.start, offset - found.chars);
+                found.newlines + buffer.newlines_in(piece.start..end)
+            }
+            None => self.pieces.newlines(),
+        };
+        let line_start = self
+            .line_start(line)
+            .expect("Line of an offset should exist");
+        (line, offset - line_start)
+    }
+
+    /// Char offset of a line and column, or `None` if it lies outside the text.
+    pub fn line_col_to_offset(&self, line: usize, col: usize) -> Option<usize> {
+        let start = self.line_start(line)?;
+        if col > self.line_length(line) {
+            return None;
+        }
+        Some(start + col)
+    }
+
+    /// Contents of a line, excluding the line break.
+    pub fn line(&self, line: usize) -> String {
+        self.lines_at(line)
+            .next()
+            .map_or_else(String::new, |line| line.to_string())
     }
 }
@@ -0,0 +1,159 @@
+use super::PieceTable;
+use std::cmp;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Handle to a position in the text that follows it through edits.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Anchor(usize);
+
+/// Which side an anchor sticks to when text is inserted right at it, or
+/// when the text around it is replaced.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Gravity {
+    /// Stays before the new text.
+    Left,
+    /// Moves after the new text.
+    Right,
+}
+
+/// Offsets of all live anchors.
+#[derive(Default)]
+pub(super) struct Anchors {
+    next: usize,
+    offsets: HashMap<Anchor, (usize, Gravity)>,
+}
+
+impl Anchors {
+    /// Moves the anchors for a range of chars replaced by `inserted` chars.
+    pub(super) fn adjust(&mut self, range: Range<usize>, inserted: usize) {
+        for (offset, gravity) in self.offsets.values_mut() {
+            let sticks_before = *offset == range.start && *gravity == Gravity::Left;
+            if *offset < range.start || sticks_before {
+                continue;
+            }
+            *offset = if *offset >= range.end && *offset > range.start {
+                *offset - range.len() + inserted
+            } else {
+                // INFO: inside the replaced text, or right at an insertion
+                match gravity {
+                    Gravity::Left => range.start,
+                    Gravity::Right => range.start + inserted,
+                }
+            };
+        }
+    }
+}
+
+impl PieceTable {
+    /// Places an anchor at a char offset, clamped to the text.
+    pub fn create_anchor(&mut self, offset: usize, gravity: Gravity) -> Anchor {
+        let anchor = Anchor(self.anchors.next);
+        self.anchors.next += 1;
+        let offset = cmp::min(offset, self.len());
+        self.anchors.offsets.insert(anchor, (offset, gravity));
+        anchor
+    }
+
+    /// Current char offset of an anchor, or `None` once it was removed.
+    pub fn anchor_offset(&self, anchor: Anchor) -> Option<usize> {
+        self.anchors.offsets.get(&anchor).map(|&(offset, _)| offset)
+    }
+
+    pub fn remove_anchor(&mut self, anchor: Anchor) {
+        self.anchors.offsets.remove(&anchor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Edit;
+    use super::*;
+
+    #[test]
+    fn gravity_decides_the_side_of_an_insertion_at_an_anchor() {
+        let mut table = PieceTable::new(String::from("abcd"));
+        let anchors = [
+            table.create_anchor(1, Gravity::Right),
+            table.create_anchor(2, Gravity::Left),
+            table.create_anchor(2, Gravity::Right),
+            table.create_anchor(3, Gravity::Left),
+        ];
+        table.insert_str("XY", 2).unwrap();
+        let offsets = anchors.map(|anchor| table.anchor_offset(anchor));
+        assert_eq!(offsets, [Some(1), Some(2), Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn anchors_in_deleted_text_collapse_to_its_start() {
+        let mut table = PieceTable::new(String::from("abcdef"));
+        let anchors = [
+            table.create_anchor(1, Gravity::Right),
+            table.create_anchor(2, Gravity::Right),
+            table.create_anchor(3, Gravity::Left),
+            table.create_anchor(4, Gravity::Right),
+            table.create_anchor(5, Gravity::Left),
+            table.create_anchor(6, Gravity::Left),
+        ];
+        table.delete_range(2..5).unwrap();
+        let offsets = anchors.map(|anchor| table.anchor_offset(anchor));
+        assert_eq!(
+            offsets,
+            [Some(1), Some(2), Some(2), Some(2), Some(2), Some(3)]
+        );
+    }
+
+    #[test]
+    fn anchors_in_replaced_text_follow_their_gravity() {
+        let mut table = PieceTable::new(String::from("abcdef"));
+        let anchors = [
+            table.create_anchor(1, Gravity::Left),
+            table.create_anchor(1, Gravity::Right),
+            table.create_anchor(2, Gravity::Left),
+            table.create_anchor(2, Gravity::Right),
+            table.create_anchor(4, Gravity::Left),
+            table.create_anchor(5, Gravity::Left),
+        ];
+        let edit = Edit {
+            range: 1..4,
+            text: String::from("XY"),
+        };
+        table.apply_edits(vec![edit]).unwrap();
+        assert_eq!(table.read(), "aXYef");
+        let offsets = anchors.map(|anchor| table.anchor_offset(anchor));
+        assert_eq!(
+            offsets,
+            [Some(1), Some(3), Some(1), Some(3), Some(3), Some(4)]
+        );
+    }
+
+    #[test]
+    fn undo_moves_anchors_back_unless_their_text_was_deleted() {
+        let mut table = PieceTable::new(String::from("abcdef"));
+        let anchors = [
+            table.create_anchor(2, Gravity::Left),
+            table.create_anchor(3, Gravity::Right),
+            table.create_anchor(5, Gravity::Left),
+        ];
+        table.delete_range(2..4).unwrap();
+        let offsets = anchors.map(|anchor| table.anchor_offset(anchor));
+        assert_eq!(offsets, [Some(2), Some(2), Some(3)]);
+
+        // INFO: undo inserts the deleted text again, and the anchor that
+        // was in it sits right at the insertion like the one before it
+        table.undo();
+        assert_eq!(table.read(), "abcdef");
+        let offsets = anchors.map(|anchor| table.anchor_offset(anchor));
+        assert_eq!(offsets, [Some(2), Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn removed_anchors_have_no_offset() {
+        let mut table = PieceTable::new(String::from("abc"));
+        let anchor = table.create_anchor(10, Gravity::Left);
+        assert_eq!(table.anchor_offset(anchor), Some(3));
+        table.remove_anchor(anchor);
+        table.insert_str("d", 0).unwrap();
+        assert_eq!(table.anchor_offset(anchor), None);
+    }
+}
@@ -0,0 +1,189 @@
+use super::tree::PieceTree;
+use super::{history, splitter, EditError, PieceTable};
+use std::ops::Range;
+
+/// Replacement of a char range with some text, one of a batch given to
+/// `apply_edits`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Edit {
+    /// Orders a batch of edits by offset, keeping insertions at the same
+    /// offset in their order, and checks that they fit a text of `len`
+    /// chars without overlapping.
+    pub(crate) fn sort(edits: &mut [Edit], len: usize) -> Result<(), EditError> {
+        edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+        let mut end = 0;
+        for edit in edits.iter() {
+            if edit.range.start > edit.range.end {
+                return Err(EditError::Reversed(edit.range.clone()));
+            }
+            if edit.range.start < end {
+                return Err(EditError::Overlapping(edit.range.clone()));
+            }
+            end = edit.range.end;
+        }
+        if end > len {
+            return Err(EditError::OutOfBounds { offset: end, len });
+        }
+        Ok(())
+    }
+}
+
+impl PieceTable {
+    /// Applies replacements whose ranges are all given against the current
+    /// text, as one undo step. The ranges must not overlap.
+    ///
+    /// The whole batch is spliced into the piece tree at once, so it takes
+    /// one tree edit however many replacements it holds.
+    pub fn apply_edits(&mut self, mut edits: Vec<Edit>) -> Result<(), EditError> {
+        self.check_loaded()?;
+        Edit::sort(&mut edits, self.len())?;
+        edits.retain(|edit| !edit.range.is_empty() || !edit.text.is_empty());
+        let (Some(first), Some(last)) = (edits.first(), edits.last()) else {
+            return Ok(());
+        };
+        let span = first.range.start..last.range.end;
+
+        let mut replacements = Vec::with_capacity(edits.len());
+        for edit in &edits {
+            replacements.push(self.push_added(&edit.text));
+        }
+        // INFO: in reverse, each edit sees the offsets and lines it would
+        // have had if the batch were applied one edit at a time
+        for (edit, inserted) in edits.iter().zip(&replacements).rev() {
+            self.anchors.adjust(edit.range.clone(), inserted.len());
+            self.queue_change(edit.range.clone(), inserted);
+        }
+
+        // INFO: the span rebuilt from the text kept between the edits and
+        // their replacements; the clone shares its nodes with the text
+        let mut inserted = PieceTree::new();
+        let mut removed = Vec::with_capacity(edits.len());
+        {
+            let split = splitter(&self.text.original, &self.text.added);
+            let mut kept = self.text.pieces.clone();
+            kept.split_off(span.end, &split);
+            let mut kept = kept.split_off(span.start, &split);
+            let mut offset = span.start;
+            for (edit, replacement) in edits.iter().zip(&replacements) {
+                let mut cut = kept.split_off(edit.range.start - offset, &split);
+                inserted.append(kept);
+                inserted.append(replacement.clone());
+                kept = cut.split_off(edit.range.len(), &split);
+                removed.push(cut);
+                offset = edit.range.end;
+            }
+            inserted.append(kept);
+        }
+        self.text.splice(span, inserted);
+
+        // INFO: one undo edit per replacement, so undo and redo move
+        // anchors and report changes as precisely as the batch did
+        self.begin_transaction();
+        let recorded = edits.iter().zip(removed).zip(replacements).rev();
+        for ((edit, removed), inserted) in recorded {
+            self.history.record(history::Edit {
+                position: edit.range.start,
+                removed,
+                inserted,
+            });
+        }
+        self.commit_transaction();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Change, Gravity};
+    use super::*;
+
+    fn change(offset: usize, removed: usize, inserted: &str, lines: [Range<usize>; 2]) -> Change {
+        let [old_lines, new_lines] = lines;
+        Change {
+            offset,
+            removed,
+            inserted: String::from(inserted),
+            old_lines,
+            new_lines,
+        }
+    }
+
+    fn batch() -> Vec<Edit> {
+        vec![
+            Edit {
+                range: 4..4,
+                text: String::from("Y"),
+            },
+            Edit {
+                range: 0..1,
+                text: String::from("X\nX"),
+            },
+            Edit {
+                range: 6..8,
+                text: String::new(),
+            },
+        ]
+    }
+
+    #[test]
+    fn apply_edits_moves_anchors_and_reports_each_edit() {
+        let mut table = PieceTable::new(String::from("ab\ncd\nef"));
+        table.watch_changes();
+        let anchors = [
+            table.create_anchor(4, Gravity::Left),
+            table.create_anchor(4, Gravity::Right),
+            table.create_anchor(5, Gravity::Left),
+            table.create_anchor(7, Gravity::Left),
+        ];
+        table.apply_edits(batch()).unwrap();
+        assert_eq!(table.read(), "X\nXb\ncYd\n");
+        let offsets = anchors.map(|anchor| table.anchor_offset(anchor));
+        assert_eq!(offsets, [Some(6), Some(7), Some(8), Some(9)]);
+        assert_eq!(
+            table.drain_changes().collect::<Vec<_>>(),
+            [
+                change(6, 2, "", [2..3, 2..3]),
+                change(4, 0, "Y", [1..2, 1..2]),
+                change(0, 1, "X\nX", [0..1, 0..2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn undoing_apply_edits_undoes_each_edit() {
+        let mut table = PieceTable::new(String::from("ab\ncd\nef"));
+        let anchors = [
+            table.create_anchor(4, Gravity::Left),
+            table.create_anchor(4, Gravity::Right),
+            table.create_anchor(5, Gravity::Left),
+            table.create_anchor(7, Gravity::Left),
+        ];
+        table.apply_edits(batch()).unwrap();
+        table.watch_changes();
+
+        assert_eq!(table.undo(), Some(6));
+        assert_eq!(table.read(), "ab\ncd\nef");
+        // INFO: the last anchor was in deleted text, which undo can not
+        // tell apart from text inserted there
+        let offsets = anchors.map(|anchor| table.anchor_offset(anchor));
+        assert_eq!(offsets, [Some(4), Some(4), Some(5), Some(6)]);
+        assert_eq!(
+            table.drain_changes().collect::<Vec<_>>(),
+            [
+                change(0, 3, "a", [0..2, 0..1]),
+                change(4, 1, "", [1..2, 1..2]),
+                change(6, 0, "ef", [2..3, 2..3]),
+            ]
+        );
+
+        table.redo();
+        assert_eq!(table.read(), "X\nXb\ncYd\n");
+        let offsets = anchors.map(|anchor| table.anchor_offset(anchor));
+        assert_eq!(offsets, [Some(6), Some(7), Some(8), Some(9)]);
+    }
+}
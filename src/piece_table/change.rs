@@ -0,0 +1,146 @@
+use super::tree::PieceTree;
+use super::PieceTable;
+use std::mem;
+use std::ops::Range;
+use std::vec;
+
+/// An edit of the text: `removed` chars at `offset` were replaced by
+/// `inserted`, turning the lines `old_lines` into the lines `new_lines`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Change {
+    pub offset: usize,
+    pub removed: usize,
+    pub inserted: String,
+    pub old_lines: Range<usize>,
+    pub new_lines: Range<usize>,
+}
+
+impl PieceTable {
+    /// Starts queueing a `Change` for every edit, undo and redo, and for
+    /// text added while a large file loads.
+    pub fn watch_changes(&mut self) {
+        self.changes.get_or_insert_with(Vec::new);
+    }
+
+    /// Takes the changes queued since the last call, oldest first.
+    pub fn drain_changes(&mut self) -> vec::IntoIter<Change> {
+        match &mut self.changes {
+            Some(changes) => mem::take(changes).into_iter(),
+            None => Vec::new().into_iter(),
+        }
+    }
+
+    /// Queues the change of replacing a range with some pieces, if changes
+    /// are watched. Must be called before the text changes.
+    pub(super) fn queue_change(&mut self, range: Range<usize>, inserted: &PieceTree) {
+        if self.changes.is_none() {
+            return;
+        }
+        let (first_line, _) = self.offset_to_line_col(range.start);
+        let (last_line, _) = self.offset_to_line_col(range.end);
+        let mut text = String::new();
+        for piece in inserted {
+            let buffer = self.buffer(piece.source);
+            text.push_str(buffer.slice(piece.start..piece.start + piece.length));
+        }
+        let change = Change {
+            offset: range.start,
+            removed: range.len(),
+            inserted: text,
+            old_lines: first_line..last_line + 1,
+            new_lines: first_line..first_line + inserted.newlines() + 1,
+        };
+        if let Some(changes) = &mut self.changes {
+            changes.push(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Edit;
+    use super::*;
+
+    fn change(offset: usize, removed: usize, inserted: &str, lines: [Range<usize>; 2]) -> Change {
+        let [old_lines, new_lines] = lines;
+        Change {
+            offset,
+            removed,
+            inserted: String::from(inserted),
+            old_lines,
+            new_lines,
+        }
+    }
+
+    fn watched(text: &str) -> PieceTable {
+        let mut table = PieceTable::new(String::from(text));
+        table.watch_changes();
+        table
+    }
+
+    #[test]
+    fn changes_are_only_queued_while_watched() {
+        let mut table = PieceTable::new(String::from("ab"));
+        table.insert_str("c", 2).unwrap();
+        assert_eq!(table.drain_changes().count(), 0);
+
+        table.watch_changes();
+        table.insert_str("d", 3).unwrap();
+        assert_eq!(table.drain_changes().count(), 1);
+        assert_eq!(table.drain_changes().count(), 0);
+    }
+
+    #[test]
+    fn insert_spans_one_old_line_and_the_new_ones() {
+        let mut table = watched("ab\ncd");
+        table.insert_str("X\nY", 4).unwrap();
+        assert_eq!(
+            table.drain_changes().collect::<Vec<_>>(),
+            [change(4, 0, "X\nY", [1..2, 1..3])]
+        );
+    }
+
+    #[test]
+    fn delete_spans_the_old_lines_it_joins() {
+        let mut table = watched("ab\ncd\nef");
+        table.delete_range(1..7).unwrap();
+        assert_eq!(table.read(), "af");
+        assert_eq!(
+            table.drain_changes().collect::<Vec<_>>(),
+            [change(1, 6, "", [0..3, 0..1])]
+        );
+    }
+
+    #[test]
+    fn replace_reports_removed_chars_and_inserted_text() {
+        let mut table = watched("ab\ncd\nef");
+        let edit = Edit {
+            range: 3..5,
+            text: String::from("X\nY"),
+        };
+        table.apply_edits(vec![edit]).unwrap();
+        assert_eq!(table.read(), "ab\nX\nY\nef");
+        assert_eq!(
+            table.drain_changes().collect::<Vec<_>>(),
+            [change(3, 2, "X\nY", [1..2, 1..3])]
+        );
+    }
+
+    #[test]
+    fn undo_and_redo_report_the_reverse_and_the_same_change() {
+        let mut table = watched("ab\ncd");
+        table.insert_str("X\nY", 4).unwrap();
+        table.drain_changes();
+
+        table.undo();
+        assert_eq!(
+            table.drain_changes().collect::<Vec<_>>(),
+            [change(4, 3, "", [1..3, 1..2])]
+        );
+        table.redo();
+        assert_eq!(
+            table.drain_changes().collect::<Vec<_>>(),
+            [change(4, 0, "X\nY", [1..2, 1..3])]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use super::tree::PieceTree;
+use super::{count_newlines, Piece, Source};
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::ptr;
+use std::slice;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Bytes a chunk of added text holds. Added pieces never span chunks, so
+/// this also bounds the text scanned to measure or split one.
+const CHUNK_SIZE: usize = 16 << 10;
+
+/// Storage of fixed size that added text is only ever appended to, so the
+/// table can keep writing after the end snapshots see of it.
+struct Chunk {
+    bytes: Box<[UnsafeCell<u8>]>,
+    /// Bytes written so far, all of them whole strings.
+    len: AtomicUsize,
+}
+
+// SAFETY: bytes below `len` are never written again, and only the one
+// `Added` allowed to append to a chunk writes above it
+unsafe impl Sync for Chunk {}
+
+impl Chunk {
+    fn new(size: usize) -> Chunk {
+        Chunk {
+            bytes: (0..size).map(|_| UnsafeCell::new(0)).collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// The text written so far.
+    fn text(&self) -> &str {
+        let len = self.len.load(Ordering::Acquire);
+        // SAFETY: the first `len` bytes were copied from whole strings
+        // before `len` was stored, and are never written again
+        unsafe {
+            let bytes = UnsafeCell::raw_get(self.bytes.as_ptr());
+            str::from_utf8_unchecked(slice::from_raw_parts(bytes, len))
+        }
+    }
+
+    /// Appends text after the text written so far and returns where it
+    /// starts, or `None` if it does not fit.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may append to the chunk at the same time.
+    unsafe fn push_str(&self, text: &str) -> Option<usize> {
+        let start = self.len.load(Ordering::Relaxed);
+        if text.len() > self.bytes.len() - start {
+            return None;
+        }
+        let end = UnsafeCell::raw_get(self.bytes[start..].as_ptr());
+        ptr::copy_nonoverlapping(text.as_ptr(), end, text.len());
+        self.len.store(start + text.len(), Ordering::Release);
+        Some(start)
+    }
+}
+
+/// The chunks of text added by edits, as seen by a table or a snapshot.
+/// Snapshots share the list and see its last chunk only up to `len`, so
+/// taking one is O(1) and typing after it still extends the last piece.
+pub(super) struct Added {
+    chunks: Arc<Vec<Arc<Chunk>>>,
+    /// Bytes of the last chunk that belong to this view.
+    len: usize,
+    /// Whether this view may append to the last chunk. Only the view that
+    /// made a chunk may, and clones never can.
+    writer: bool,
+}
+
+impl Default for Added {
+    fn default() -> Added {
+        Added {
+            chunks: Arc::default(),
+            len: 0,
+            writer: true,
+        }
+    }
+}
+
+impl Clone for Added {
+    fn clone(&self) -> Added {
+        Added {
+            chunks: Arc::clone(&self.chunks),
+            len: self.len,
+            writer: false,
+        }
+    }
+}
+
+impl Added {
+    /// Added text with one chunk holding each of `texts`, for loading text
+    /// that pieces already point into.
+    pub(super) fn from_texts(texts: Vec<String>) -> Added {
+        let mut added = Added::default();
+        for text in texts {
+            let chunk = Arc::new(Chunk::new(text.len()));
+            // SAFETY: nothing else has the new chunk yet
+            unsafe { chunk.push_str(&text) };
+            Arc::make_mut(&mut added.chunks).push(chunk);
+            added.len = text.len();
+        }
+        added
+    }
+
+    /// Number of chunks.
+    pub(super) fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The text of a chunk this view sees.
+    pub(super) fn text(&self, chunk: usize) -> &str {
+        let text = self.chunks[chunk].text();
+        match chunk + 1 == self.chunks.len() {
+            true => &text[..self.len],
+            false => text,
+        }
+    }
+
+    /// Appends text and returns pieces covering it. Text larger than a
+    /// chunk is cut on char boundaries, one piece per chunk.
+    pub(super) fn push_str(&mut self, mut text: &str) -> PieceTree {
+        let mut pieces = PieceTree::new();
+        while !text.is_empty() {
+            let mut end = cmp::min(text.len(), CHUNK_SIZE);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            let (head, rest) = text.split_at(end);
+            pieces.push(self.push(head));
+            text = rest;
+        }
+        pieces
+    }
+
+    /// Appends text without cutting it, starting a new chunk if it does not
+    /// fit in the last one, and returns a piece covering it.
+    pub(super) fn push(&mut self, text: &str) -> Piece {
+        let last = self.chunks.last().filter(|_| self.writer);
+        // SAFETY: only the view that made the last chunk writes to it
+        let start = match last.and_then(|chunk| unsafe { chunk.push_str(text) }) {
+            Some(start) => start,
+            None => {
+                let chunk = Arc::new(Chunk::new(cmp::max(text.len(), CHUNK_SIZE)));
+                // SAFETY: nothing else has the new chunk yet
+                unsafe { chunk.push_str(text) };
+                Arc::make_mut(&mut self.chunks).push(chunk);
+                self.writer = true;
+                0
+            }
+        };
+        self.len = start + text.len();
+        Piece {
+            source: Source::Added(self.chunks.len() - 1),
+            start,
+            length: text.len(),
+            chars: text.chars().count(),
+            newlines: count_newlines(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::PieceTable;
+    use super::*;
+
+    #[test]
+    fn typing_after_a_snapshot_extends_the_last_piece() {
+        let mut table = PieceTable::new(String::from("start\n"));
+        let mut snapshots = Vec::new();
+        for i in 0..100 {
+            snapshots.push(table.snapshot());
+            table.insert('x', 6 + i).unwrap();
+        }
+        assert_eq!(table.added.len(), 1);
+        assert_eq!(table.pieces.iter().count(), 2);
+        for (i, snapshot) in snapshots.iter().enumerate() {
+            assert_eq!(snapshot.read(), format!("start\n{}", "x".repeat(i)));
+            assert_eq!(snapshot.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn text_larger_than_a_chunk_is_cut_on_char_boundaries() {
+        let text = "é".repeat(CHUNK_SIZE);
+        let mut table = PieceTable::new(String::new());
+        table.insert_str("ab", 0).unwrap();
+        table.insert_str(&text, 1).unwrap();
+        assert_eq!(table.added.len(), 3);
+        assert_eq!(table.read(), format!("a{}b", text));
+        assert_eq!(table.validate(), Ok(()));
+    }
+}
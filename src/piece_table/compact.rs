@@ -0,0 +1,125 @@
+use super::tree::PieceTree;
+use super::{Added, Piece, PieceTable, Source};
+use std::cmp;
+
+/// Bytes of unreachable added text below which compaction is not worth it.
+const MIN_COMPACT: usize = 1 << 20;
+
+/// How much text of the added chunks became unreachable since they were
+/// last compacted, and how much may before the next compaction.
+pub(super) struct Compaction {
+    wasted: usize,
+    threshold: usize,
+}
+
+impl Default for Compaction {
+    fn default() -> Compaction {
+        Compaction {
+            wasted: 0,
+            threshold: MIN_COMPACT,
+        }
+    }
+}
+
+/// Where a run of live text of an old chunk ended up in the new ones.
+struct Moved {
+    chunk: usize,
+    start: usize,
+    new_chunk: usize,
+    new_start: usize,
+}
+
+/// Byte ranges of the added chunks used by some pieces, merged where they
+/// overlap or touch and ordered by buffer and offset.
+fn live_ranges<'a>(pieces: impl Iterator<Item = &'a Piece>) -> Vec<(usize, usize, usize)> {
+    let mut ranges: Vec<_> = pieces
+        .filter_map(|piece| match piece.source {
+            Source::Added(i) => Some((i, piece.start, piece.start + piece.length)),
+            Source::Original(_) => None,
+        })
+        .collect();
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize, usize)> = Vec::new();
+    for (i, start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if last.0 == i && start <= last.2 => last.2 = cmp::max(last.2, end),
+            _ => merged.push((i, start, end)),
+        }
+    }
+    merged
+}
+
+/// Rebuilds a tree with every added piece pointed at the new chunks.
+/// Pushing the pieces again joins any that became contiguous.
+fn remap(tree: &PieceTree, moved: &[Moved]) -> PieceTree {
+    let mut remapped = PieceTree::new();
+    for piece in tree {
+        let Source::Added(chunk) = piece.source else {
+            remapped.push(piece.clone());
+            continue;
+        };
+        let i = moved.partition_point(|m| (m.chunk, m.start) <= (chunk, piece.start));
+        let run = &moved[i - 1];
+        remapped.push(Piece {
+            source: Source::Added(run.new_chunk),
+            start: run.new_start + piece.start - run.start,
+            ..piece.clone()
+        });
+    }
+    remapped
+}
+
+impl PieceTable {
+    /// Keeps at most `limit` undo steps before the current state, or all of
+    /// them with `None`, and drops the text only older steps used.
+    pub fn set_history_limit(&mut self, limit: Option<usize>) {
+        self.history.set_limit(limit);
+        self.compact();
+    }
+
+    /// Rewrites the added chunks into new ones that hold only the text still
+    /// used by the pieces and the undo history. Snapshots keep the chunks
+    /// they were taken with.
+    pub fn compact(&mut self) {
+        self.history.take_dropped();
+        let mut trees: Vec<&mut PieceTree> = self.history.trees_mut().collect();
+        trees.push(&mut self.text.pieces);
+        let ranges = live_ranges(trees.iter().flat_map(|tree| tree.iter()));
+
+        // INFO: runs are pushed whole, since the pieces in them must not
+        // end up spanning two chunks
+        let mut added = Added::default();
+        let mut live = 0;
+        let mut moved = Vec::with_capacity(ranges.len());
+        for (i, start, end) in ranges {
+            let piece = added.push(&self.text.added.text(i)[start..end]);
+            live += piece.length;
+            moved.push(Moved {
+                chunk: i,
+                start,
+                new_chunk: added.len() - 1,
+                new_start: piece.start,
+            });
+        }
+        for tree in trees {
+            *tree = remap(tree, &moved);
+        }
+
+        self.text.added = added;
+        self.compaction = Compaction {
+            wasted: 0,
+            threshold: cmp::max(live, MIN_COMPACT),
+        };
+    }
+
+    /// Counts the added text the undo history let go of, compacting the
+    /// added chunks once about as much of them is unreachable as is live.
+    /// Only a history limit makes text unreachable, so without one this
+    /// never compacts.
+    pub(super) fn count_dropped(&mut self) {
+        self.compaction.wasted += self.history.take_dropped();
+        if self.compaction.wasted >= self.compaction.threshold {
+            self.compact();
+        }
+    }
+}
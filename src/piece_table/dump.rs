@@ -0,0 +1,165 @@
+use super::anchor::Anchors;
+use super::compact::Compaction;
+use super::history::{History, SavedHistory};
+use super::tree::PieceTree;
+use super::{buffer, Added, Buffer, Piece, PieceTable, Snapshot, Source};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Everything a piece table holds, in a storable form: the text of every
+/// buffer and chunk, the pieces of the text, and the undo tree with the pieces of
+/// each edit listed in `edit_pieces`.
+#[derive(Serialize, Deserialize)]
+struct Dump {
+    original: Vec<String>,
+    added: Vec<String>,
+    pieces: Vec<SavedPiece>,
+    edit_pieces: Vec<SavedPiece>,
+    history: SavedHistory,
+}
+
+/// A piece without its cached metrics, which are recounted on load.
+#[derive(Serialize, Deserialize)]
+struct SavedPiece {
+    source: Source,
+    start: usize,
+    length: usize,
+}
+
+fn save_pieces(tree: &PieceTree, saved: &mut Vec<SavedPiece>) -> Range<usize> {
+    let start = saved.len();
+    saved.extend(tree.iter().map(|piece| SavedPiece {
+        source: piece.source,
+        start: piece.start,
+        length: piece.length,
+    }));
+    start..saved.len()
+}
+
+/// Rebuilds pieces from their saved form, or `None` if one does not lie
+/// on char boundaries of an existing buffer or chunk.
+fn load_pieces(saved: &[SavedPiece], original: &[Arc<Buffer>], added: &Added) -> Option<PieceTree> {
+    let mut tree = PieceTree::new();
+    for piece in saved {
+        let exists = match piece.source {
+            Source::Original(i) => i < original.len(),
+            Source::Added(i) => i < added.len(),
+        };
+        if !exists {
+            return None;
+        }
+        let buffer = buffer(original, added, piece.source);
+        let range = piece.start..piece.start.checked_add(piece.length)?;
+        buffer.text.get(range.clone())?;
+        tree.push(Piece {
+            source: piece.source,
+            start: piece.start,
+            length: piece.length,
+            chars: buffer.chars_in(range.clone()),
+            newlines: buffer.newlines_in(range),
+        });
+    }
+    Some(tree)
+}
+
+impl PieceTable {
+    /// Serializes the whole table as JSON, so that `from_dump` can recreate
+    /// it exactly elsewhere. Edits of a transaction still open are left out.
+    pub fn dump(&self) -> String {
+        let mut pieces = Vec::new();
+        save_pieces(&self.pieces, &mut pieces);
+        let mut edit_pieces = Vec::new();
+        let history = self
+            .history
+            .save(|tree| save_pieces(tree, &mut edit_pieces));
+        let dump = Dump {
+            original: self
+                .original
+                .iter()
+                .map(|buffer| buffer.text().to_string())
+                .collect(),
+            added: (0..self.added.len())
+                .map(|chunk| self.added.text(chunk).to_string())
+                .collect(),
+            pieces,
+            edit_pieces,
+            history,
+        };
+        serde_json::to_string_pretty(&dump).expect("Dump should serialize to JSON")
+    }
+
+    /// Recreates a table from the output of `dump`.
+    pub fn from_dump(dump: &str) -> io::Result<PieceTable> {
+        let dump: Dump = serde_json::from_str(dump)?;
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "Malformed dump");
+        let original: Vec<_> = dump
+            .original
+            .into_iter()
+            .map(|text| Arc::new(Buffer::new(text)))
+            .collect();
+        let added = Added::from_texts(dump.added);
+        let pieces = load_pieces(&dump.pieces, &original, &added).ok_or_else(malformed)?;
+        let history = History::load(dump.history, |range| {
+            load_pieces(dump.edit_pieces.get(range)?, &original, &added)
+        })
+        .ok_or_else(malformed)?;
+        Ok(PieceTable {
+            text: Snapshot {
+                original: Arc::new(original),
+                added,
+                pieces,
+            },
+            history,
+            anchors: Anchors::default(),
+            changes: None,
+            compaction: Compaction::default(),
+            loader: None,
+            not_utf8: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edited() -> PieceTable {
+        let mut table = PieceTable::new(String::from("one\ntwo\n"));
+        table.insert_str("zero\n", 0).unwrap();
+        table.delete_range(5..9).unwrap();
+        table.undo();
+        table.insert_str("é", 9).unwrap();
+        table
+    }
+
+    #[test]
+    fn from_dump_restores_text_and_history() {
+        let mut table = edited();
+        let dump = table.dump();
+        let mut restored = PieceTable::from_dump(&dump).unwrap();
+        assert_eq!(restored.read(), table.read());
+        assert_eq!(restored.validate(), Ok(()));
+        assert_eq!(restored.dump(), dump);
+
+        assert_eq!(restored.switch_branch(), table.switch_branch());
+        assert_eq!(restored.read(), "zero\ntwo\n");
+        loop {
+            let undone = table.undo();
+            assert_eq!(restored.undo(), undone);
+            assert_eq!(restored.read(), table.read());
+            if undone.is_none() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn from_dump_rejects_pieces_outside_their_buffer() {
+        let mut dump: serde_json::Value = serde_json::from_str(&edited().dump()).unwrap();
+        dump["pieces"][0]["length"] = serde_json::Value::from(1000);
+        let error = PieceTable::from_dump(&dump.to_string()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
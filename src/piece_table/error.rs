@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// Why an edit was refused. The text is left unchanged.
+#[derive(Clone, PartialEq, Debug)]
+pub enum EditError {
+    /// A char offset lies past the end of the text, which is `len` long.
+    OutOfBounds { offset: usize, len: usize },
+    /// A range ends before it starts.
+    Reversed(Range<usize>),
+    /// A range of a batch overlaps the one before it.
+    Overlapping(Range<usize>),
+    /// The rest of the file is still loading, or failed to, so the text
+    /// may yet be decoded differently.
+    Loading,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::OutOfBounds { offset, len } => {
+                write!(f, "offset {} is past the end at {}", offset, len)
+            }
+            EditError::Reversed(range) => {
+                write!(f, "range {:?} ends before it starts", range)
+            }
+            EditError::Overlapping(range) => {
+                write!(f, "range {:?} overlaps another edit", range)
+            }
+            EditError::Loading => write!(f, "the rest of the file is not loaded yet"),
+        }
+    }
+}
+
+impl Error for EditError {}
+
+/// Fails unless `range` is a valid range of a text of `len` chars.
+pub(crate) fn check_range(len: usize, range: Range<usize>) -> Result<(), EditError> {
+    if range.start > range.end {
+        return Err(EditError::Reversed(range));
+    }
+    if range.end > len {
+        return Err(EditError::OutOfBounds {
+            offset: range.end,
+            len,
+        });
+    }
+    Ok(())
+}
+
+/// A broken invariant of a piece table, with the index of the piece it was
+/// found at.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Corruption {
+    /// The piece covers no text.
+    EmptyPiece(usize),
+    /// The piece reaches past its buffer or does not lie on char
+    /// boundaries.
+    OutOfRange(usize),
+    /// The piece continues the one before it in the same buffer, so the two
+    /// should have been joined.
+    Mergeable(usize),
+    /// The cached char or newline count of the piece is wrong.
+    WrongMetrics(usize),
+    /// The totals cached in the piece tree do not add up.
+    WrongTotals,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Corruption::EmptyPiece(i) => write!(f, "piece {} is empty", i),
+            Corruption::OutOfRange(i) => write!(f, "piece {} lies outside its buffer", i),
+            Corruption::Mergeable(i) => write!(f, "piece {} continues the one before", i),
+            Corruption::WrongMetrics(i) => write!(f, "piece {} has wrong counts", i),
+            Corruption::WrongTotals => write!(f, "piece tree totals are wrong"),
+        }
+    }
+}
+
+impl Error for Corruption {}
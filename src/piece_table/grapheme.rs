@@ -0,0 +1,97 @@
+use super::Snapshot;
+use std::cmp;
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+impl Snapshot {
+    /// Char offset of the first grapheme cluster boundary after `offset`.
+    pub fn next_grapheme_boundary(&self, offset: usize) -> usize {
+        let total = self.pieces.bytes();
+        let byte = self.char_to_byte(offset);
+        let mut cursor = GraphemeCursor::new(byte, total, true);
+        let Some((mut chunk, mut chunk_start)) = self.chunk_at_byte(byte) else {
+            return self.len();
+        };
+        loop {
+            match cursor.next_boundary(chunk, chunk_start) {
+                Ok(Some(boundary)) => return self.byte_to_char(boundary),
+                Ok(None) => return self.len(),
+                Err(GraphemeIncomplete::NextChunk) => {
+                    chunk_start += chunk.len();
+                    chunk = self
+                        .chunk_at_byte(chunk_start)
+                        .map_or("", |(chunk, _)| chunk);
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => self.provide_context(&mut cursor, end),
+                Err(_) => return cmp::min(offset + 1, self.len()),
+            }
+        }
+    }
+
+    /// Char offset of the last grapheme cluster boundary before `offset`.
+    pub fn prev_grapheme_boundary(&self, offset: usize) -> usize {
+        let total = self.pieces.bytes();
+        let byte = self.char_to_byte(offset);
+        let mut cursor = GraphemeCursor::new(byte, total, true);
+        let Some((mut chunk, mut chunk_start)) = self.chunk_at_byte(byte.saturating_sub(1)) else {
+            return 0;
+        };
+        loop {
+            match cursor.prev_boundary(chunk, chunk_start) {
+                Ok(Some(boundary)) => return self.byte_to_char(boundary),
+                Ok(None) => return 0,
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    let prev = chunk_start
+                        .checked_sub(1)
+                        .and_then(|byte| self.chunk_at_byte(byte));
+                    match prev {
+                        Some(prev) => (chunk, chunk_start) = prev,
+                        None => return offset.saturating_sub(1),
+                    }
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => self.provide_context(&mut cursor, end),
+                Err(_) => return offset.saturating_sub(1),
+            }
+        }
+    }
+
+    fn provide_context(&self, cursor: &mut GraphemeCursor, end: usize) {
+        if let Some((chunk, chunk_start)) = self.chunk_at_byte(end - 1) {
+            cursor.provide_context(chunk, chunk_start);
+        }
+    }
+
+    /// The whole piece containing a byte offset, with the byte offset it
+    /// starts at.
+    fn chunk_at_byte(&self, byte: usize) -> Option<(&str, usize)> {
+        let found = self.pieces.find_byte(byte)?;
+        let piece = found.piece;
+        let chunk = self
+            .buffer(piece.source)
+            .slice(piece.start..piece.start + piece.length);
+        Some((chunk, found.bytes))
+    }
+
+    /// Byte offset of a char offset in the text.
+    fn char_to_byte(&self, offset: usize) -> usize {
+        match self.pieces.find(offset) {
+            Some(found) => {
+                let piece = found.piece;
+                let buffer = self.buffer(piece.source);
+                found.bytes + buffer.advance(piece.start, offset - found.chars) - piece.start
+            }
+            None => self.pieces.bytes(),
+        }
+    }
+
+    /// Char offset of a byte offset in the text.
+    fn byte_to_char(&self, byte: usize) -> usize {
+        match self.pieces.find_byte(byte) {
+            Some(found) => {
+                let piece = found.piece;
+                let buffer = self.buffer(piece.source);
+                found.chars + buffer.chars_in(piece.start..piece.start + byte - found.bytes)
+            }
+            None => self.len(),
+        }
+    }
+}
@@ -0,0 +1,537 @@
+use super::tree::PieceTree;
+use super::Source;
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::collections::HashSet;
+use std::mem;
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A single replacement: the pieces `removed` at `position` were replaced
+/// by the pieces `inserted`. Both sides are kept so the edit can be undone
+/// and redone, and only reference the buffers, so the memory used is
+/// proportional to the edit rather than to the document.
+#[derive(Clone)]
+pub(super) struct Edit {
+    pub(super) position: usize,
+    pub(super) removed: PieceTree,
+    pub(super) inserted: PieceTree,
+}
+
+/// A document state in the undo tree, reached from its parent by applying
+/// `edits`. States are numbered in creation order, so their index doubles
+/// as a timeline.
+struct State {
+    parent: usize,
+    edits: Vec<Edit>,
+    children: Vec<usize>,
+    active_child: Option<usize>,
+    time: SystemTime,
+}
+
+/// A group of edits to apply to move one step through the undo tree.
+pub(super) enum Step {
+    Undo(Vec<Edit>),
+    Redo(Vec<Edit>),
+}
+
+/// Undo tree in a storable form, where the pieces of every edit are
+/// replaced by a range of text in a separate buffer.
+#[derive(Serialize, Deserialize)]
+pub(super) struct SavedHistory {
+    current: usize,
+    states: Vec<SavedState>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedState {
+    parent: usize,
+    active_child: Option<usize>,
+    time: u64,
+    edits: Vec<SavedEdit>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEdit {
+    position: usize,
+    removed: Range<usize>,
+    inserted: Range<usize>,
+}
+
+/// Bytes of the add buffers referenced by some edits.
+fn added_bytes(edits: &[Edit]) -> usize {
+    edits
+        .iter()
+        .flat_map(|edit| edit.removed.iter().chain(edit.inserted.iter()))
+        .filter(|piece| matches!(piece.source, Source::Added(_)))
+        .map(|piece| piece.length)
+        .sum()
+}
+
+/// Undo tree of grouped edits. Typing after an undo starts a new branch
+/// instead of discarding the undone states. With a `limit`, states more
+/// than that many undo steps above the current one are dropped, and
+/// `dropped` counts the bytes of added text their edits referenced.
+pub(super) struct History {
+    states: Vec<State>,
+    current: usize,
+    pending: Vec<Edit>,
+    depth: usize,
+    limit: Option<usize>,
+    dropped: usize,
+}
+
+impl Default for History {
+    fn default() -> History {
+        History {
+            states: vec![State {
+                parent: 0,
+                edits: Vec::new(),
+                children: Vec::new(),
+                active_child: None,
+                time: SystemTime::now(),
+            }],
+            current: 0,
+            pending: Vec::new(),
+            depth: 0,
+            limit: None,
+            dropped: 0,
+        }
+    }
+}
+
+impl History {
+    pub(super) fn record(&mut self, edit: Edit) {
+        self.pending.push(edit);
+        if self.depth == 0 {
+            self.close();
+        }
+    }
+
+    pub(super) fn begin(&mut self) {
+        self.depth += 1;
+    }
+
+    pub(super) fn commit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            self.close();
+        }
+    }
+
+    /// Commits the pending group as a new state, even if transactions are
+    /// still open.
+    pub(super) fn close(&mut self) {
+        self.depth = 0;
+        if self.pending.is_empty() {
+            return;
+        }
+        let index = self.states.len();
+        self.states.push(State {
+            parent: self.current,
+            edits: mem::take(&mut self.pending),
+            children: Vec::new(),
+            active_child: None,
+            time: SystemTime::now(),
+        });
+        let parent = &mut self.states[self.current];
+        parent.children.push(index);
+        parent.active_child = Some(index);
+        self.current = index;
+        self.prune();
+    }
+
+    pub(super) fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub(super) fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+        self.prune();
+    }
+
+    /// Takes the bytes of added text referenced by the edits dropped since
+    /// the last call. Text shared with kept edits is counted too, so this
+    /// is an upper bound of what became unreachable.
+    pub(super) fn take_dropped(&mut self) -> usize {
+        mem::take(&mut self.dropped)
+    }
+
+    /// Makes the state `limit` undo steps above the current one the root,
+    /// dropping everything that does not descend from it.
+    fn prune(&mut self) {
+        let Some(limit) = self.limit else {
+            return;
+        };
+        let mut root = self.current;
+        for _ in 0..limit {
+            if root == 0 {
+                return;
+            }
+            root = self.states[root].parent;
+        }
+        if root == 0 {
+            return;
+        }
+
+        // INFO: parents are created before their children, so one pass in
+        // creation order finds every descendant and keeps the timeline
+        let mut index = vec![None; self.states.len()];
+        let mut kept = 0;
+        for i in root..self.states.len() {
+            if i == root || index[self.states[i].parent].is_some() {
+                index[i] = Some(kept);
+                kept += 1;
+            }
+        }
+        let states = mem::take(&mut self.states);
+        for (i, mut state) in states.into_iter().enumerate() {
+            if index[i].is_none() {
+                self.dropped += added_bytes(&state.edits);
+                continue;
+            }
+            if i == root {
+                self.dropped += added_bytes(&state.edits);
+                state.parent = 0;
+                state.edits.clear();
+            } else {
+                state.parent = index[state.parent].expect("Parent of a kept state is kept");
+            }
+            state.children = state.children.iter().filter_map(|&c| index[c]).collect();
+            state.active_child = state.active_child.and_then(|c| index[c]);
+            self.states.push(state);
+        }
+        self.current = index[self.current].expect("Current state is kept");
+    }
+
+    /// All pieces held by the undo tree, including edits not yet committed.
+    pub(super) fn trees_mut(&mut self) -> impl Iterator<Item = &mut PieceTree> {
+        self.states
+            .iter_mut()
+            .flat_map(|state| state.edits.iter_mut())
+            .chain(self.pending.iter_mut())
+            .flat_map(|edit| [&mut edit.removed, &mut edit.inserted])
+    }
+
+    /// Steps back to the parent state.
+    pub(super) fn undo(&mut self) -> Vec<Step> {
+        self.close();
+        let parent = self.states[self.current].parent;
+        self.travel(parent)
+    }
+
+    /// Steps forward along the most recently used branch.
+    pub(super) fn redo(&mut self) -> Vec<Step> {
+        self.close();
+        match self.states[self.current].active_child {
+            Some(child) => self.travel(child),
+            None => Vec::new(),
+        }
+    }
+
+    /// Moves to the state created just before the current one.
+    pub(super) fn earlier(&mut self) -> Vec<Step> {
+        self.close();
+        self.travel(self.current.saturating_sub(1))
+    }
+
+    /// Moves to the state created just after the current one.
+    pub(super) fn later(&mut self) -> Vec<Step> {
+        self.close();
+        self.travel(cmp::min(self.current + 1, self.states.len() - 1))
+    }
+
+    /// Moves to the next sibling of the current state, cycling through the
+    /// branches that fork from the parent.
+    pub(super) fn switch_branch(&mut self) -> Vec<Step> {
+        self.close();
+        let siblings = &self.states[self.states[self.current].parent].children;
+        match siblings.iter().position(|&i| i == self.current) {
+            Some(i) if siblings.len() > 1 => {
+                let next = siblings[(i + 1) % siblings.len()];
+                self.travel(next)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Moves to the latest state that existed at `time`.
+    pub(super) fn at_time(&mut self, time: SystemTime) -> Vec<Step> {
+        self.close();
+        let target = self
+            .states
+            .iter()
+            .rposition(|state| state.time <= time)
+            .unwrap_or(0);
+        self.travel(target)
+    }
+
+    /// Returns the steps leading from the current state to `target` through
+    /// their closest common ancestor, and makes `target` current.
+    fn travel(&mut self, target: usize) -> Vec<Step> {
+        let mut ancestors = HashSet::new();
+        let mut state = self.current;
+        loop {
+            ancestors.insert(state);
+            if state == 0 {
+                break;
+            }
+            state = self.states[state].parent;
+        }
+
+        let mut down = Vec::new();
+        let mut common = target;
+        while !ancestors.contains(&common) {
+            down.push(common);
+            common = self.states[common].parent;
+        }
+
+        let mut steps = Vec::new();
+        let mut state = self.current;
+        while state != common {
+            let parent = self.states[state].parent;
+            self.states[parent].active_child = Some(state);
+            steps.push(Step::Undo(self.states[state].edits.clone()));
+            state = parent;
+        }
+        for &state in down.iter().rev() {
+            let parent = self.states[state].parent;
+            self.states[parent].active_child = Some(state);
+            steps.push(Step::Redo(self.states[state].edits.clone()));
+        }
+        self.current = target;
+        steps
+    }
+
+    /// Converts the undo tree to its storable form, using `store` to turn
+    /// the pieces of each edit into a range of saved text.
+    pub(super) fn save<F>(&self, mut store: F) -> SavedHistory
+    where
+        F: FnMut(&PieceTree) -> Range<usize>,
+    {
+        let states = self
+            .states
+            .iter()
+            .map(|state| SavedState {
+                parent: state.parent,
+                active_child: state.active_child,
+                time: state
+                    .time
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_millis() as u64),
+                edits: state
+                    .edits
+                    .iter()
+                    .map(|edit| SavedEdit {
+                        position: edit.position,
+                        removed: store(&edit.removed),
+                        inserted: store(&edit.inserted),
+                    })
+                    .collect(),
+            })
+            .collect();
+        SavedHistory {
+            current: self.current,
+            states,
+        }
+    }
+
+    /// Rebuilds an undo tree from its storable form, using `restore` to turn
+    /// saved text ranges back into pieces. Returns `None` if the saved tree
+    /// is malformed.
+    pub(super) fn load<F>(saved: SavedHistory, mut restore: F) -> Option<History>
+    where
+        F: FnMut(Range<usize>) -> Option<PieceTree>,
+    {
+        let count = saved.states.len();
+        if saved.current >= count {
+            return None;
+        }
+        let mut states = Vec::with_capacity(count);
+        for (i, state) in saved.states.into_iter().enumerate() {
+            if (i > 0 && state.parent >= i) || state.active_child.is_some_and(|c| c >= count) {
+                return None;
+            }
+            let mut edits = Vec::with_capacity(state.edits.len());
+            for edit in state.edits {
+                edits.push(Edit {
+                    position: edit.position,
+                    removed: restore(edit.removed)?,
+                    inserted: restore(edit.inserted)?,
+                });
+            }
+            states.push(State {
+                parent: if i == 0 { 0 } else { state.parent },
+                edits,
+                children: Vec::new(),
+                active_child: state.active_child,
+                time: UNIX_EPOCH + Duration::from_millis(state.time),
+            });
+        }
+        for i in 1..count {
+            let parent = states[i].parent;
+            states[parent].children.push(i);
+        }
+        Some(History {
+            states,
+            current: saved.current,
+            pending: Vec::new(),
+            depth: 0,
+            limit: None,
+            dropped: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Piece;
+    use super::*;
+
+    /// An edit told apart by its position, with nothing removed or inserted.
+    fn edit(position: usize) -> Edit {
+        Edit {
+            position,
+            removed: PieceTree::new(),
+            inserted: PieceTree::new(),
+        }
+    }
+
+    /// An edit inserting `length` bytes of added text.
+    fn insertion(position: usize, length: usize) -> Edit {
+        let mut inserted = PieceTree::new();
+        inserted.push(Piece {
+            source: Source::Added(0),
+            start: position,
+            length,
+            chars: length,
+            newlines: 0,
+        });
+        Edit {
+            position,
+            removed: PieceTree::new(),
+            inserted,
+        }
+    }
+
+    /// Each step as whether it undoes, and the positions of its edits.
+    fn positions(steps: Vec<Step>) -> Vec<(bool, Vec<usize>)> {
+        steps
+            .into_iter()
+            .map(|step| match step {
+                Step::Undo(edits) => (true, edits),
+                Step::Redo(edits) => (false, edits),
+            })
+            .map(|(undo, edits)| (undo, edits.iter().map(|edit| edit.position).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn transactions_group_edits_into_one_state() {
+        let mut history = History::default();
+        history.begin();
+        history.record(edit(1));
+        history.record(edit(2));
+        history.commit();
+        assert_eq!(positions(history.undo()), [(true, vec![1, 2])]);
+        assert_eq!(positions(history.undo()), []);
+        assert_eq!(positions(history.redo()), [(false, vec![1, 2])]);
+    }
+
+    #[test]
+    fn travel_goes_through_the_common_ancestor() {
+        let mut history = History::default();
+        history.record(edit(1));
+        history.record(edit(2));
+        history.undo();
+        history.record(edit(3));
+
+        assert_eq!(
+            positions(history.switch_branch()),
+            [(true, vec![3]), (false, vec![2])]
+        );
+        assert_eq!(
+            positions(history.later()),
+            [(true, vec![2]), (false, vec![3])]
+        );
+        assert_eq!(positions(history.later()), []);
+        assert_eq!(
+            positions(history.earlier()),
+            [(true, vec![3]), (false, vec![2])]
+        );
+    }
+
+    #[test]
+    fn redo_follows_the_last_visited_branch() {
+        let mut history = History::default();
+        history.record(edit(1));
+        history.undo();
+        history.record(edit(2));
+        history.switch_branch();
+        history.undo();
+        assert_eq!(positions(history.redo()), [(false, vec![1])]);
+    }
+
+    #[test]
+    fn at_time_finds_the_latest_state_by_then() {
+        let mut history = History::default();
+        history.record(edit(1));
+        let time = history.states[1].time;
+        history.record(edit(2));
+        history.states[2].time = time + Duration::from_secs(1);
+
+        assert_eq!(positions(history.at_time(time)), [(true, vec![2])]);
+        assert_eq!(positions(history.at_time(UNIX_EPOCH)), [(true, vec![1])]);
+    }
+
+    #[test]
+    fn prune_keeps_limit_steps_of_undo() {
+        let mut history = History::default();
+        history.set_limit(Some(2));
+        for position in 1..=5 {
+            history.record(edit(position));
+        }
+        assert_eq!(positions(history.undo()), [(true, vec![5])]);
+        assert_eq!(positions(history.undo()), [(true, vec![4])]);
+        assert_eq!(positions(history.undo()), []);
+        assert_eq!(history.states.len(), 3);
+        assert_eq!(positions(history.earlier()), []);
+    }
+
+    #[test]
+    fn prune_drops_branches_off_the_new_root() {
+        let mut history = History::default();
+        history.set_limit(Some(2));
+        history.record(edit(1));
+        history.undo();
+        history.record(edit(2));
+        assert_eq!(
+            positions(history.switch_branch()),
+            [(true, vec![2]), (false, vec![1])]
+        );
+        history.switch_branch();
+
+        history.record(edit(3));
+        history.record(edit(4));
+        assert_eq!(history.states.len(), 3);
+        assert_eq!(positions(history.undo()), [(true, vec![4])]);
+        assert_eq!(positions(history.undo()), [(true, vec![3])]);
+        assert_eq!(positions(history.switch_branch()), []);
+        assert_eq!(positions(history.undo()), []);
+    }
+
+    #[test]
+    fn prune_counts_the_added_text_it_drops() {
+        let mut history = History::default();
+        for (position, length) in [(0, 10), (10, 20), (30, 30), (60, 40)] {
+            history.record(insertion(position, length));
+        }
+        assert_eq!(history.take_dropped(), 0);
+
+        history.set_limit(Some(1));
+        assert_eq!(history.take_dropped(), 60);
+        assert_eq!(history.take_dropped(), 0);
+        history.record(insertion(100, 50));
+        assert_eq!(history.take_dropped(), 40);
+    }
+}
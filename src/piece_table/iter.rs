@@ -0,0 +1,182 @@
+use super::tree::Iter;
+use super::Snapshot;
+use std::cmp;
+use std::fmt;
+use std::ops::Range;
+
+/// Borrowed view of a char range of a `Snapshot`. Displaying it streams
+/// the text piece by piece without building a `String`.
+#[derive(Clone, Copy)]
+pub struct Slice<'a> {
+    table: &'a Snapshot,
+    start: usize,
+    end: usize,
+}
+
+/// Iterator over the text of a `Slice` as one `&str` per piece.
+pub struct Chunks<'a> {
+    table: &'a Snapshot,
+    pieces: Iter<'a>,
+    skip: usize,
+    remaining: usize,
+}
+
+/// Cursor over the chars of a `Snapshot` that can move in both
+/// directions: `next` returns the char after the cursor and `prev` the
+/// char before it.
+pub struct Chars<'a> {
+    table: &'a Snapshot,
+    offset: usize,
+    chunk: &'a str,
+    index: usize,
+}
+
+/// Iterator over lines as `Slice`s, excluding their line breaks.
+pub struct Lines<'a> {
+    table: &'a Snapshot,
+    line: usize,
+}
+
+impl<'a> Slice<'a> {
+    pub(super) fn new(table: &'a Snapshot, range: Range<usize>) -> Slice<'a> {
+        let end = cmp::min(range.end, table.len());
+        Slice {
+            table,
+            start: cmp::min(range.start, end),
+            end,
+        }
+    }
+
+    /// Number of chars in the slice.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn chunks(&self) -> Chunks<'a> {
+        let (pieces, skip) = self.table.pieces.iter_from(self.start);
+        Chunks {
+            table: self.table,
+            pieces,
+            skip,
+            remaining: self.len(),
+        }
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + 'a {
+        self.chunks().flat_map(str::chars)
+    }
+}
+
+impl fmt::Display for Slice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in self.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let piece = self.pieces.next()?;
+        let buffer = self.table.buffer(piece.source);
+        let take = cmp::min(piece.chars - self.skip, self.remaining);
+        let start = match self.skip {
+            0 => piece.start,
+            skip => buffer.advance(piece.start, skip),
+        };
+        let end = match piece.chars - self.skip - take {
+            0 => piece.start + piece.length,
+            _ => buffer.advance(start, take),
+        };
+        let chunk = buffer.slice(start..end);
+        self.skip = 0;
+        self.remaining -= take;
+        Some(chunk)
+    }
+}
+
+impl<'a> Chars<'a> {
+    pub(super) fn new(table: &'a Snapshot, offset: usize) -> Chars<'a> {
+        Chars {
+            table,
+            offset: cmp::min(offset, table.len()),
+            chunk: "",
+            index: 0,
+        }
+    }
+
+    /// Char offset of the cursor.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Points `chunk` at the piece containing the char at `offset` and moves
+    /// `index` to the cursor within it.
+    fn load(&mut self, offset: usize) -> bool {
+        match self.table.pieces.find(offset) {
+            Some(found) => {
+                let piece = found.piece;
+                let buffer = self.table.buffer(piece.source);
+                self.chunk = buffer.slice(piece.start..piece.start + piece.length);
+                self.index = buffer.advance(piece.start, self.offset - found.chars) - piece.start;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the char before the cursor and moves the cursor back.
+    pub fn prev(&mut self) -> Option<char> {
+        if self.offset == 0 {
+            return None;
+        }
+        if self.index == 0 && !self.load(self.offset - 1) {
+            return None;
+        }
+        let c = self.chunk[..self.index].chars().next_back()?;
+        self.index -= c.len_utf8();
+        self.offset -= 1;
+        Some(c)
+    }
+}
+
+impl Iterator for Chars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.index == self.chunk.len() && !self.load(self.offset) {
+            return None;
+        }
+        let c = self.chunk[self.index..].chars().next()?;
+        self.index += c.len_utf8();
+        self.offset += 1;
+        Some(c)
+    }
+}
+
+impl<'a> Lines<'a> {
+    pub(super) fn new(table: &'a Snapshot, line: usize) -> Lines<'a> {
+        Lines { table, line }
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = Slice<'a>;
+
+    fn next(&mut self) -> Option<Slice<'a>> {
+        let start = self.table.line_start(self.line)?;
+        let length = self.table.line_length(self.line);
+        self.line += 1;
+        Some(Slice::new(self.table, start..start + length))
+    }
+}
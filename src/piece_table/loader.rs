@@ -0,0 +1,173 @@
+use super::history::History;
+use super::tree::PieceTree;
+use super::anchor::Anchors;
+use super::compact::Compaction;
+use super::{Added, Buffer, EditError, PieceTable, Snapshot, Source};
+use memmap2::Mmap;
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+/// Files at least this large are mapped and indexed in the background.
+const LAZY_THRESHOLD: u64 = 8 << 20;
+
+/// Bytes indexed before a mapped file is shown, plenty for a first screen.
+const FIRST_BLOCK: usize = 256 << 10;
+
+/// Bytes indexed at a time by the background loader.
+const BLOCK: usize = 4 << 20;
+
+/// Checks and indexes the block of the mapping starting at `start`, ending
+/// it on a char boundary. Returns a buffer over the block, or the byte
+/// offset at which it is not UTF-8, and where the next block starts.
+fn next_block(map: &Arc<Mmap>, start: usize, size: usize) -> (Result<Buffer, usize>, usize) {
+    let mut end = cmp::min(start + size, map.len());
+    // INFO: UTF-8 continuation bytes look like 0b10xxxxxx
+    while end > start && end < map.len() && map[end] & 0xC0 == 0x80 {
+        end -= 1;
+    }
+    if end == start {
+        end = cmp::min(start + size, map.len());
+    }
+    (Buffer::mapped(Arc::clone(map), start..end), end)
+}
+
+/// Error for a mapped file that is not UTF-8 from byte offset `at` on.
+fn not_utf8(at: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("not UTF-8 at byte {}", at),
+    )
+}
+
+/// Indexes the rest of the mapping on another thread, block by block,
+/// stopping at the first block that is not UTF-8.
+fn spawn(map: Arc<Mmap>, mut start: usize) -> Receiver<Result<Buffer, usize>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while start < map.len() {
+            let block;
+            (block, start) = next_block(&map, start, BLOCK);
+            let invalid = block.is_err();
+            // INFO: the table was dropped, nobody is waiting for the rest
+            if sender.send(block).is_err() || invalid {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+impl PieceTable {
+    /// Opens a file. Large files are memory-mapped: the text at the start
+    /// is available right away and the rest is added at the end of the
+    /// table as `poll_loading` picks it up.
+    ///
+    /// The file must not be changed by others while it is open, and must
+    /// be saved by replacing it rather than writing into it. Fails with
+    /// `InvalidData` if the start of the file is not UTF-8.
+    pub fn open(path: &Path) -> io::Result<PieceTable> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() < LAZY_THRESHOLD {
+            return Ok(PieceTable::new(io::read_to_string(file)?));
+        }
+
+        // SAFETY: the mapping is only read, see the requirements above
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+        let mut table = PieceTable {
+            text: Snapshot {
+                original: Arc::default(),
+                added: Added::default(),
+                pieces: PieceTree::new(),
+            },
+            history: History::default(),
+            anchors: Anchors::default(),
+            changes: None,
+            compaction: Compaction::default(),
+            loader: None,
+            not_utf8: None,
+        };
+        let (first, start) = next_block(&map, 0, FIRST_BLOCK);
+        table.add_block(first.map_err(not_utf8)?);
+        if start < map.len() {
+            table.loader = Some(spawn(map, start));
+        }
+        Ok(table)
+    }
+
+    /// Whether part of the file is still being indexed.
+    pub fn is_loading(&self) -> bool {
+        self.loader.is_some()
+    }
+
+    /// Adds the text indexed since the last call to the end of the table.
+    /// Returns whether the text or the loading state changed, or an error
+    /// once the rest of the file turned out not to be UTF-8. The table
+    /// then holds only the text before it.
+    pub fn poll_loading(&mut self) -> io::Result<bool> {
+        let mut changed = false;
+        while let Some(loader) = &self.loader {
+            match loader.try_recv() {
+                Ok(block) => self.receive(block),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.loader = None,
+            }
+            changed = true;
+        }
+        self.loaded().map(|()| changed)
+    }
+
+    /// Waits until the whole file is indexed and added. Fails like
+    /// `poll_loading` if it is not all UTF-8.
+    pub fn finish_loading(&mut self) -> io::Result<()> {
+        if let Some(loader) = self.loader.take() {
+            for block in loader {
+                self.receive(block);
+            }
+        }
+        self.loaded()
+    }
+
+    /// Refuses edits until the whole file is loaded as UTF-8, since a file
+    /// that turns out not to be is decoded again from scratch.
+    pub(super) fn check_loaded(&self) -> Result<(), EditError> {
+        match self.loader.is_some() || self.not_utf8.is_some() {
+            true => Err(EditError::Loading),
+            false => Ok(()),
+        }
+    }
+
+    /// Fails if loading stopped short of the end of the file.
+    fn loaded(&self) -> io::Result<()> {
+        match self.not_utf8 {
+            Some(at) => Err(not_utf8(at)),
+            None => Ok(()),
+        }
+    }
+
+    fn receive(&mut self, block: Result<Buffer, usize>) {
+        match block {
+            Ok(buffer) => self.add_block(buffer),
+            Err(at) => {
+                self.not_utf8 = Some(at);
+                self.loader = None;
+            }
+        }
+    }
+
+    /// Appends loaded text after everything else. Edits made meanwhile
+    /// all lie before it, so their offsets stay valid.
+    fn add_block(&mut self, buffer: Buffer) {
+        let source = Source::Original(self.text.original.len());
+        let mut block = PieceTree::new();
+        block.push(buffer.piece(source));
+        Arc::make_mut(&mut self.text.original).push(Arc::new(buffer));
+        let end = self.len();
+        self.queue_change(end..end, &block);
+        self.text.pieces.append(block);
+    }
+}
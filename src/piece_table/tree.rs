@@ -0,0 +1,461 @@
+use super::Piece;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+type Link = Option<Arc<Node>>;
+
+/// Nodes an iterator keeps without allocating. Random priorities keep the
+/// treap about 3 log2 n deep, so only trees of astronomically many pieces
+/// or a freak run of priorities go deeper.
+const STACK: usize = 96;
+
+/// Balanced piece tree: a treap ordered by document position where every
+/// node caches the character and newline counts of its subtree.
+///
+/// Nodes are shared through `Arc` and copied on write, so cloning a tree
+/// is O(1) and edits only copy the O(log n) nodes along the touched path.
+#[derive(Clone, Default)]
+pub(super) struct PieceTree {
+    root: Link,
+}
+
+/// A piece located in the tree, with the totals of everything before it.
+pub(super) struct Found<'a> {
+    pub(super) piece: &'a Piece,
+    pub(super) chars: usize,
+    pub(super) bytes: usize,
+    pub(super) newlines: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Metric {
+    Chars,
+    Bytes,
+    Newlines,
+}
+
+impl Metric {
+    fn of_piece(self, piece: &Piece) -> usize {
+        match self {
+            Metric::Chars => piece.chars,
+            Metric::Bytes => piece.length,
+            Metric::Newlines => piece.newlines,
+        }
+    }
+
+    fn of_link(self, link: &Link) -> usize {
+        match self {
+            Metric::Chars => chars(link),
+            Metric::Bytes => bytes(link),
+            Metric::Newlines => newlines(link),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Node {
+    piece: Piece,
+    priority: u64,
+    chars: usize,
+    bytes: usize,
+    newlines: usize,
+    left: Link,
+    right: Link,
+}
+
+fn next_priority() -> u64 {
+    // INFO: splitmix64 over a global counter, good enough for treap balance
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut z = COUNTER
+        .fetch_add(1, Ordering::Relaxed)
+        .wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn chars(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.chars)
+}
+
+fn bytes(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.bytes)
+}
+
+fn newlines(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.newlines)
+}
+
+fn leaf(piece: Piece) -> Link {
+    Some(Arc::new(Node {
+        chars: piece.chars,
+        bytes: piece.length,
+        newlines: piece.newlines,
+        piece,
+        priority: next_priority(),
+        left: None,
+        right: None,
+    }))
+}
+
+fn update(node: &mut Node) {
+    node.chars = chars(&node.left) + node.piece.chars + chars(&node.right);
+    node.bytes = bytes(&node.left) + node.piece.length + bytes(&node.right);
+    node.newlines = newlines(&node.left) + node.piece.newlines + newlines(&node.right);
+}
+
+fn merge(left: Link, right: Link) -> Link {
+    match (left, right) {
+        (None, right) => right,
+        (left, None) => left,
+        (Some(mut l), Some(mut r)) => {
+            if l.priority > r.priority {
+                let node = Arc::make_mut(&mut l);
+                node.right = merge(node.right.take(), Some(r));
+                update(node);
+                Some(l)
+            } else {
+                let node = Arc::make_mut(&mut r);
+                node.left = merge(Some(l), node.left.take());
+                update(node);
+                Some(r)
+            }
+        }
+    }
+}
+
+/// Splits `link` so that the first `offset` characters end up on the left,
+/// cutting the piece that straddles `offset` in two with `split_piece`.
+fn split<F>(link: Link, offset: usize, split_piece: &F) -> (Link, Link)
+where
+    F: Fn(&Piece, usize) -> (Piece, Piece),
+{
+    let Some(mut link) = link else {
+        return (None, None);
+    };
+    let node = Arc::make_mut(&mut link);
+    let left_chars = chars(&node.left);
+    if offset <= left_chars {
+        let (a, b) = split(node.left.take(), offset, split_piece);
+        node.left = b;
+        update(node);
+        (a, Some(link))
+    } else if offset >= left_chars + node.piece.chars {
+        let rest = offset - left_chars - node.piece.chars;
+        let (a, b) = split(node.right.take(), rest, split_piece);
+        node.right = a;
+        update(node);
+        (Some(link), b)
+    } else {
+        let (head, tail) = split_piece(&node.piece, offset - left_chars);
+        node.piece = head;
+        let right = node.right.take();
+        update(node);
+        (Some(link), merge(leaf(tail), right))
+    }
+}
+
+/// Whether the totals cached in every node of a subtree add up, and its
+/// priorities are in heap order.
+fn is_consistent(link: &Link) -> bool {
+    let Some(node) = link else {
+        return true;
+    };
+    let ordered = [&node.left, &node.right].iter().all(|child| {
+        child
+            .as_ref()
+            .is_none_or(|child| child.priority <= node.priority)
+    });
+    let mut expected = node.as_ref().clone();
+    update(&mut expected);
+    ordered
+        && (expected.chars, expected.bytes, expected.newlines)
+            == (node.chars, node.bytes, node.newlines)
+        && is_consistent(&node.left)
+        && is_consistent(&node.right)
+}
+
+fn pop_last(link: &mut Link) -> Option<Piece> {
+    let mut taken = link.take()?;
+    let node = Arc::make_mut(&mut taken);
+    if node.right.is_some() {
+        let piece = pop_last(&mut node.right);
+        update(node);
+        *link = Some(taken);
+        piece
+    } else {
+        *link = node.left.take();
+        Some(node.piece.clone())
+    }
+}
+
+fn pop_first(link: &mut Link) -> Option<Piece> {
+    let mut taken = link.take()?;
+    let node = Arc::make_mut(&mut taken);
+    if node.left.is_some() {
+        let piece = pop_first(&mut node.left);
+        update(node);
+        *link = Some(taken);
+        piece
+    } else {
+        *link = node.right.take();
+        Some(node.piece.clone())
+    }
+}
+
+impl PieceTree {
+    pub(super) fn new() -> PieceTree {
+        PieceTree { root: None }
+    }
+
+    /// Total number of characters covered by the pieces.
+    pub(super) fn len(&self) -> usize {
+        chars(&self.root)
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Total number of bytes covered by the pieces.
+    pub(super) fn bytes(&self) -> usize {
+        bytes(&self.root)
+    }
+
+    /// Total number of newlines covered by the pieces.
+    pub(super) fn newlines(&self) -> usize {
+        newlines(&self.root)
+    }
+
+    /// Finds the piece containing the char at `offset`.
+    pub(super) fn find(&self, offset: usize) -> Option<Found<'_>> {
+        self.locate(offset, Metric::Chars)
+    }
+
+    /// Finds the piece containing the byte at `offset`.
+    pub(super) fn find_byte(&self, offset: usize) -> Option<Found<'_>> {
+        self.locate(offset, Metric::Bytes)
+    }
+
+    /// Finds the piece containing the `n`th newline, counting from one.
+    pub(super) fn find_newline(&self, n: usize) -> Option<Found<'_>> {
+        self.locate(n.checked_sub(1)?, Metric::Newlines)
+    }
+
+    /// Descends to the piece where the running total of `metric` passes
+    /// `target`.
+    fn locate(&self, mut target: usize, metric: Metric) -> Option<Found<'_>> {
+        let mut link = &self.root;
+        let (mut chars_before, mut bytes_before, mut newlines_before) = (0, 0, 0);
+        while let Some(node) = link {
+            let left = metric.of_link(&node.left);
+            if target < left {
+                link = &node.left;
+                continue;
+            }
+            chars_before += chars(&node.left);
+            bytes_before += bytes(&node.left);
+            newlines_before += newlines(&node.left);
+            if target < left + metric.of_piece(&node.piece) {
+                return Some(Found {
+                    piece: &node.piece,
+                    chars: chars_before,
+                    bytes: bytes_before,
+                    newlines: newlines_before,
+                });
+            }
+            target -= left + metric.of_piece(&node.piece);
+            chars_before += node.piece.chars;
+            bytes_before += node.piece.length;
+            newlines_before += node.piece.newlines;
+            link = &node.right;
+        }
+        None
+    }
+
+    pub(super) fn push(&mut self, piece: Piece) {
+        if piece.chars > 0 {
+            self.append(PieceTree { root: leaf(piece) });
+        }
+    }
+
+    /// Concatenates two trees, joining the pieces at the seam when they are
+    /// contiguous in the same buffer.
+    pub(super) fn append(&mut self, mut other: PieceTree) {
+        let seam = match (pop_last(&mut self.root), pop_first(&mut other.root)) {
+            (Some(last), Some(first)) => match last.merged(&first) {
+                Some(piece) => leaf(piece),
+                None => merge(leaf(last), leaf(first)),
+            },
+            (last, first) => merge(last.and_then(leaf), first.and_then(leaf)),
+        };
+        self.root = merge(merge(self.root.take(), seam), other.root.take());
+    }
+
+    /// Splits the tree at a character offset, returning everything after it.
+    pub(super) fn split_off<F>(&mut self, offset: usize, split_piece: &F) -> PieceTree
+    where
+        F: Fn(&Piece, usize) -> (Piece, Piece),
+    {
+        let (left, right) = split(self.root.take(), offset, split_piece);
+        self.root = left;
+        PieceTree { root: right }
+    }
+
+    /// Whether the totals cached in the tree match its pieces.
+    pub(super) fn is_consistent(&self) -> bool {
+        is_consistent(&self.root)
+    }
+
+    pub(super) fn iter(&self) -> Iter<'_> {
+        let mut iter = Iter::new();
+        iter.push_left(&self.root);
+        iter
+    }
+
+    /// Iterates from the piece containing `offset`, also returning how far
+    /// into that piece `offset` lies.
+    pub(super) fn iter_from(&self, offset: usize) -> (Iter<'_>, usize) {
+        let mut iter = Iter::new();
+        let (mut link, mut offset) = (&self.root, offset);
+        while let Some(node) = link {
+            let left_chars = chars(&node.left);
+            if offset < left_chars {
+                iter.push(node);
+                link = &node.left;
+            } else if offset < left_chars + node.piece.chars {
+                iter.push(node);
+                return (iter, offset - left_chars);
+            } else {
+                offset -= left_chars + node.piece.chars;
+                link = &node.right;
+            }
+        }
+        (iter, 0)
+    }
+}
+
+impl<'a> IntoIterator for &'a PieceTree {
+    type Item = &'a Piece;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// In-order iterator over the pieces of a `PieceTree`. The path to the
+/// next piece is kept on a fixed stack, which only spills onto the heap
+/// for trees deeper than `STACK`.
+pub(super) struct Iter<'a> {
+    stack: [Option<&'a Node>; STACK],
+    len: usize,
+    spill: Vec<&'a Node>,
+}
+
+impl<'a> Iter<'a> {
+    fn new() -> Iter<'a> {
+        Iter {
+            stack: [None; STACK],
+            len: 0,
+            spill: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, node: &'a Node) {
+        if self.len < STACK {
+            self.stack[self.len] = Some(node);
+            self.len += 1;
+        } else {
+            self.spill.push(node);
+        }
+    }
+
+    fn pop(&mut self) -> Option<&'a Node> {
+        if let Some(node) = self.spill.pop() {
+            return Some(node);
+        }
+        self.len = self.len.checked_sub(1)?;
+        self.stack[self.len].take()
+    }
+
+    fn push_left(&mut self, mut link: &'a Link) {
+        while let Some(node) = link {
+            self.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Piece;
+
+    fn next(&mut self) -> Option<&'a Piece> {
+        let node = self.pop()?;
+        self.push_left(&node.right);
+        Some(&node.piece)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Buffer, Source};
+    use super::*;
+
+    /// Byte start, byte length, chars and newlines of every piece.
+    fn spans(tree: &PieceTree) -> Vec<(usize, usize, usize, usize)> {
+        tree.iter()
+            .map(|piece| (piece.start, piece.length, piece.chars, piece.newlines))
+            .collect()
+    }
+
+    #[test]
+    fn split_off_divides_the_metrics() {
+        let buffer = Buffer::new(String::from("ab\né\ncd\n"));
+        let split = |piece: &Piece, chars| buffer.indexed().split_piece(piece, chars);
+        let mut tree = PieceTree::new();
+        tree.push(buffer.piece(Source::Original(0)));
+
+        let tail = tree.split_off(4, &split);
+        assert_eq!((tree.len(), tree.bytes(), tree.newlines()), (4, 5, 1));
+        assert_eq!((tail.len(), tail.bytes(), tail.newlines()), (4, 4, 2));
+        assert_eq!(spans(&tree), [(0, 5, 4, 1)]);
+        assert_eq!(spans(&tail), [(5, 4, 4, 2)]);
+        assert!(tree.is_consistent() && tail.is_consistent());
+    }
+
+    #[test]
+    fn append_joins_contiguous_pieces_at_the_seam() {
+        let buffer = Buffer::new(String::from("one\ntwo\nthree\n"));
+        let split = |piece: &Piece, chars| buffer.indexed().split_piece(piece, chars);
+        let mut tree = PieceTree::new();
+        tree.push(buffer.piece(Source::Original(0)));
+        let mut middle = tree.split_off(4, &split);
+        let tail = middle.split_off(4, &split);
+
+        tree.append(middle);
+        tree.append(tail);
+        assert_eq!(spans(&tree), [(0, 14, 14, 3)]);
+        assert!(tree.is_consistent());
+    }
+
+    #[test]
+    fn append_keeps_pieces_that_are_not_contiguous() {
+        let buffer = Buffer::new(String::from("one\ntwo\nthree\n"));
+        let split = |piece: &Piece, chars| buffer.indexed().split_piece(piece, chars);
+        let mut tree = PieceTree::new();
+        tree.push(buffer.piece(Source::Original(0)));
+        let mut middle = tree.split_off(4, &split);
+        let tail = middle.split_off(4, &split);
+
+        tree.append(tail);
+        tree.append(middle);
+        assert_eq!(spans(&tree), [(0, 4, 4, 1), (8, 6, 6, 1), (4, 4, 4, 1)]);
+        assert_eq!((tree.len(), tree.bytes(), tree.newlines()), (14, 14, 3));
+        assert!(tree.is_consistent());
+
+        let found = tree.find_newline(2).expect("Tree has a second newline");
+        assert_eq!((found.chars, found.bytes, found.newlines), (4, 4, 1));
+        assert_eq!(found.piece.start, 8);
+    }
+}
@@ -0,0 +1,74 @@
+use super::history::{History, SavedHistory};
+use super::PieceTable;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Undo history stored next to a file. The text of every edit is kept in
+/// `add_buffer`, since the original buffer it may point to is replaced by
+/// the saved file on the next load.
+#[derive(Serialize, Deserialize)]
+struct UndoFile {
+    checksum: u64,
+    add_buffer: String,
+    history: SavedHistory,
+}
+
+/// FNV-1a hash of the text, used to tell whether a file changed on disk
+/// since its undo history was saved.
+fn checksum(table: &PieceTable) -> u64 {
+    table
+        .chunks()
+        .flat_map(str::bytes)
+        .fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+        })
+}
+
+impl PieceTable {
+    /// Writes the undo history to `path`, tied to the current text.
+    pub fn save_history(&self, path: &Path) -> io::Result<()> {
+        let mut add_buffer = String::new();
+        let history = self.history.save(|pieces| {
+            let start = add_buffer.len();
+            for piece in pieces {
+                let buffer = self.buffer(piece.source);
+                add_buffer.push_str(buffer.slice(piece.start..piece.start + piece.length));
+            }
+            start..add_buffer.len()
+        });
+        let undo_file = UndoFile {
+            checksum: checksum(self),
+            add_buffer,
+            history,
+        };
+        fs::write(path, serde_json::to_string(&undo_file)?)
+    }
+
+    /// Replaces the undo history with the one saved at `path`. Returns
+    /// `false` without changing anything if the text no longer matches it.
+    pub fn load_history(&mut self, path: &Path) -> io::Result<bool> {
+        let undo_file: UndoFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if undo_file.checksum != checksum(self) {
+            return Ok(false);
+        }
+
+        let text = &undo_file.add_buffer;
+        let history = History::load(undo_file.history, |range| {
+            Some(self.push_added(text.get(range)?))
+        });
+        match history {
+            Some(mut history) => {
+                history.set_limit(self.history.limit());
+                self.history = history;
+                self.count_dropped();
+                Ok(true)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Malformed undo history",
+            )),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use super::{Corruption, Piece, Snapshot, Source};
+
+impl Snapshot {
+    /// Checks that every piece covers some text on char boundaries of an
+    /// existing buffer, that no two neighbours could be joined, and that
+    /// the cached counts match the text. Debug builds check this after
+    /// every edit.
+    pub fn validate(&self) -> Result<(), Corruption> {
+        let mut previous = None;
+        for (i, piece) in self.pieces.iter().enumerate() {
+            if piece.length == 0 {
+                return Err(Corruption::EmptyPiece(i));
+            }
+            let exists = match piece.source {
+                Source::Original(b) => b < self.original.len(),
+                Source::Added(b) => b < self.added.len(),
+            };
+            if !exists {
+                return Err(Corruption::OutOfRange(i));
+            }
+            let buffer = self.buffer(piece.source);
+            let range = piece.start..piece.start + piece.length;
+            if buffer.text.get(range.clone()).is_none() {
+                return Err(Corruption::OutOfRange(i));
+            }
+            if piece.chars != buffer.chars_in(range.clone())
+                || piece.newlines != buffer.newlines_in(range)
+            {
+                return Err(Corruption::WrongMetrics(i));
+            }
+            if previous.is_some_and(|previous: &Piece| previous.merged(piece).is_some()) {
+                return Err(Corruption::Mergeable(i));
+            }
+            previous = Some(piece);
+        }
+        if !self.pieces.is_consistent() {
+            return Err(Corruption::WrongTotals);
+        }
+        Ok(())
+    }
+}
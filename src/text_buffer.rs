@@ -0,0 +1,457 @@
+use crate::piece_table::{check_range, Edit, EditError, PieceTable, Snapshot};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+pub use gap::GapBuffer;
+pub use rope::RopeBuffer;
+
+mod gap;
+mod rope;
+mod undo;
+
+/// Text storage the editor works on. Offsets are in chars and lines are
+/// separated by `\n`, with `\r\n` counted as one line break.
+///
+/// Implementations provide editing with undo and a few basic lookups; the
+/// rest has default implementations built on those, which backends may
+/// replace with faster ones.
+pub trait TextBuffer {
+    /// Inserts text at a char offset.
+    fn insert_str(&mut self, text: &str, position: usize) -> Result<(), EditError>;
+
+    /// Deletes a char range.
+    fn delete_range(&mut self, range: Range<usize>) -> Result<(), EditError>;
+
+    /// Groups all following edits into one undo step until the matching
+    /// `commit_transaction`. Transactions may be nested.
+    fn begin_transaction(&mut self);
+
+    fn commit_transaction(&mut self);
+
+    /// Reverts the last undo step and returns the offset it started at.
+    fn undo(&mut self) -> Option<usize>;
+
+    /// Reapplies the last undone step and returns the offset it ended at.
+    fn redo(&mut self) -> Option<usize>;
+
+    /// Number of chars in the text.
+    fn len(&self) -> usize;
+
+    /// Iterates over the text of a char range, clamped to the text, as a
+    /// series of `&str`.
+    fn chunks(&self, range: Range<usize>) -> Box<dyn Iterator<Item = &str> + '_>;
+
+    /// Number of lines, counting the (possibly empty) line after the last newline.
+    fn line_count(&self) -> usize;
+
+    /// Char offset of the first character of a line.
+    fn line_start(&self, line: usize) -> Option<usize>;
+
+    fn insert(&mut self, insert_char: char, position: usize) -> Result<(), EditError> {
+        self.insert_str(insert_char.encode_utf8(&mut [0; 4]), position)
+    }
+
+    /// Deletes the grapheme cluster starting at `position`, if any.
+    fn delete(&mut self, position: usize) -> Result<(), EditError> {
+        check_range(self.len(), position..position)?;
+        let end = self.next_grapheme_boundary(position);
+        self.delete_range(position..end)
+    }
+
+    /// Applies replacements whose ranges are all given against the current
+    /// text, as one undo step. The ranges must not overlap; insertions at
+    /// the same offset keep their order.
+    fn apply_edits(&mut self, mut edits: Vec<Edit>) -> Result<(), EditError> {
+        Edit::sort(&mut edits, self.len())?;
+        self.begin_transaction();
+        // INFO: back to front, so the offsets still to come stay valid
+        for edit in edits.iter().rev() {
+            self.delete_range(edit.range.clone())?;
+            self.insert_str(&edit.text, edit.range.start)?;
+        }
+        self.commit_transaction();
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn read(&self) -> String {
+        self.chunks(0..self.len()).collect()
+    }
+
+    /// Number of chars on a line, excluding the line break, be it `\n` or
+    /// `\r\n`.
+    fn line_length(&self, line: usize) -> usize {
+        let Some(start) = self.line_start(line) else {
+            return 0;
+        };
+        let Some(next) = self.line_start(line + 1) else {
+            return self.len() - start;
+        };
+        let end = next - 1;
+        let before_newline = self
+            .chunks(end.saturating_sub(1)..end)
+            .flat_map(str::chars)
+            .next();
+        if end > start && before_newline == Some('\r') {
+            end - 1 - start
+        } else {
+            end - start
+        }
+    }
+
+    /// Contents of a line, excluding the line break.
+    fn line(&self, line: usize) -> String {
+        match self.line_start(line) {
+            Some(start) => self.chunks(start..start + self.line_length(line)).collect(),
+            None => String::new(),
+        }
+    }
+
+    fn offset_to_line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.len());
+        let (mut low, mut high) = (0, self.line_count() - 1);
+        while low < high {
+            let middle = (low + high).div_ceil(2);
+            match self.line_start(middle) {
+                Some(start) if start <= offset => low = middle,
+                _ => high = middle - 1,
+            }
+        }
+        let start = self.line_start(low).unwrap_or(0);
+        (low, offset - start)
+    }
+
+    /// Char offset of a line and column, or `None` if it lies outside the text.
+    fn line_col_to_offset(&self, line: usize, col: usize) -> Option<usize> {
+        let start = self.line_start(line)?;
+        if col > self.line_length(line) {
+            return None;
+        }
+        Some(start + col)
+    }
+
+    /// Char offset of the first grapheme cluster boundary after `offset`.
+    fn next_grapheme_boundary(&self, offset: usize) -> usize {
+        let (line, column) = self.offset_to_line_col(offset);
+        let (start, text) = line_with_break(self, line);
+        match line_boundary(&text, column, true) {
+            Some(next) => start + next,
+            None => self.len(),
+        }
+    }
+
+    /// Char offset of the last grapheme cluster boundary before `offset`.
+    fn prev_grapheme_boundary(&self, offset: usize) -> usize {
+        let (line, column) = self.offset_to_line_col(offset);
+        if column == 0 {
+            // INFO: a line break is the last cluster of the line before
+            return match line {
+                0 => 0,
+                line => self.line_start(line - 1).unwrap_or(0) + self.line_length(line - 1),
+            };
+        }
+        let (start, text) = line_with_break(self, line);
+        start + line_boundary(&text, column, false).unwrap_or(0)
+    }
+
+    /// Char offset of the end of the word after `offset`, skipping
+    /// whitespace before it. A line break is a word of its own.
+    fn next_word_boundary(&self, offset: usize) -> usize {
+        let (line, column) = self.offset_to_line_col(offset);
+        if column == self.line_length(line) {
+            return self.next_grapheme_boundary(offset);
+        }
+        let start = self.line_start(line).unwrap_or(0);
+        start + word_boundary(&self.line(line), column, true)
+    }
+
+    /// Char offset of the start of the word before `offset`, skipping
+    /// whitespace after it. A line break is a word of its own.
+    fn prev_word_boundary(&self, offset: usize) -> usize {
+        let (line, column) = self.offset_to_line_col(offset);
+        if column == 0 {
+            return self.prev_grapheme_boundary(offset);
+        }
+        let start = self.line_start(line).unwrap_or(0);
+        start + word_boundary(&self.line(line), column, false)
+    }
+
+    /// Moves to the previous state in time, if the backend keeps them.
+    fn earlier(&mut self) -> Option<usize> {
+        None
+    }
+
+    /// Moves to the next state in time, if the backend keeps them.
+    fn later(&mut self) -> Option<usize> {
+        None
+    }
+
+    /// Moves to the next sibling undo branch, if the backend keeps them.
+    fn switch_branch(&mut self) -> Option<usize> {
+        None
+    }
+
+    /// Moves to the state the text was in at `time`, if the backend keeps
+    /// them.
+    fn at_time(&mut self, _time: SystemTime) -> Option<usize> {
+        None
+    }
+
+    /// Keeps at most `limit` undo steps, or all of them with `None`, if the
+    /// backend keeps an undo tree.
+    fn set_history_limit(&mut self, _limit: Option<usize>) {}
+
+    /// Writes the undo history to `path`, if the backend can store it.
+    fn save_history(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Replaces the undo history with the one saved at `path`. Returns
+    /// `false` if it was not loaded.
+    fn load_history(&mut self, _path: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Whether part of the file is still being read in the background.
+    /// Edits are refused until it is.
+    fn is_loading(&self) -> bool {
+        false
+    }
+
+    /// Adds text read in the background. Returns whether anything changed,
+    /// or an error once the rest of the file can not be read.
+    fn poll_loading(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Waits until the whole file is read.
+    fn finish_loading(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Complete internal state for replaying a bug elsewhere, if the
+    /// backend can serialize it.
+    fn debug_dump(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Start of a line and its text including the line break, which no
+/// grapheme cluster crosses.
+fn line_with_break<T: TextBuffer + ?Sized>(text: &T, line: usize) -> (usize, String) {
+    let start = text.line_start(line).unwrap_or(text.len());
+    let end = text.line_start(line + 1).unwrap_or(text.len());
+    (start, text.chunks(start..end).collect())
+}
+
+/// Char column of the grapheme cluster boundary after (or before) `column`
+/// within a line, or `None` if there is none.
+fn line_boundary(line: &str, column: usize, next: bool) -> Option<usize> {
+    let byte = line
+        .char_indices()
+        .nth(column)
+        .map_or(line.len(), |(i, _)| i);
+    let mut cursor = GraphemeCursor::new(byte, line.len(), true);
+    let boundary = match next {
+        true => cursor.next_boundary(line, 0),
+        false => cursor.prev_boundary(line, 0),
+    };
+    let boundary = boundary.ok().flatten()?;
+    Some(line[..boundary].chars().count())
+}
+
+/// Storage a `TextBuffer` can be created with.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Backend {
+    PieceTable,
+    Rope,
+    GapBuffer,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "piece-table" => Some(Backend::PieceTable),
+            "rope" => Some(Backend::Rope),
+            "gap-buffer" => Some(Backend::GapBuffer),
+            _ => None,
+        }
+    }
+
+    pub fn create(&self, text: String) -> Box<dyn TextBuffer> {
+        match self {
+            Backend::PieceTable => Box::new(PieceTable::new(text)),
+            Backend::Rope => Box::new(RopeBuffer::new(&text)),
+            Backend::GapBuffer => Box::new(GapBuffer::new(&text)),
+        }
+    }
+}
+
+/// Kinds of grapheme clusters a word is made of.
+#[derive(Copy, Clone, PartialEq)]
+enum WordClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+impl WordClass {
+    fn of(grapheme: &str) -> WordClass {
+        match grapheme.chars().next() {
+            Some(c) if c.is_whitespace() => WordClass::Space,
+            Some(c) if c.is_alphanumeric() || c == '_' => WordClass::Word,
+            _ => WordClass::Punctuation,
+        }
+    }
+}
+
+/// Char column of the end (or start) of the run of word or punctuation
+/// clusters after (or before) `column` within a line.
+fn word_boundary(line: &str, column: usize, next: bool) -> usize {
+    let mut clusters = Vec::new();
+    let mut start = 0;
+    for grapheme in line.graphemes(true) {
+        let end = start + grapheme.chars().count();
+        clusters.push((start, end, WordClass::of(grapheme)));
+        start = end;
+    }
+    let clusters: Box<dyn Iterator<Item = _>> = match next {
+        true => Box::new(clusters.into_iter().filter(|&(start, _, _)| start >= column)),
+        false => Box::new(clusters.into_iter().rev().filter(|&(_, end, _)| end <= column)),
+    };
+    let mut boundary = column;
+    let mut word = None;
+    for (start, end, class) in clusters {
+        match word {
+            None if class != WordClass::Space => word = Some(class),
+            Some(word) if word != class => break,
+            _ => {}
+        }
+        boundary = if next { end } else { start };
+    }
+    boundary
+}
+
+impl TextBuffer for PieceTable {
+    fn insert_str(&mut self, text: &str, position: usize) -> Result<(), EditError> {
+        PieceTable::insert_str(self, text, position)
+    }
+
+    fn delete_range(&mut self, range: Range<usize>) -> Result<(), EditError> {
+        PieceTable::delete_range(self, range)
+    }
+
+    fn delete(&mut self, position: usize) -> Result<(), EditError> {
+        PieceTable::delete(self, position)
+    }
+
+    fn apply_edits(&mut self, edits: Vec<Edit>) -> Result<(), EditError> {
+        PieceTable::apply_edits(self, edits)
+    }
+
+    fn begin_transaction(&mut self) {
+        PieceTable::begin_transaction(self);
+    }
+
+    fn commit_transaction(&mut self) {
+        PieceTable::commit_transaction(self);
+    }
+
+    fn undo(&mut self) -> Option<usize> {
+        PieceTable::undo(self)
+    }
+
+    fn redo(&mut self) -> Option<usize> {
+        PieceTable::redo(self)
+    }
+
+    fn len(&self) -> usize {
+        Snapshot::len(self)
+    }
+
+    fn chunks(&self, range: Range<usize>) -> Box<dyn Iterator<Item = &str> + '_> {
+        Box::new(self.slice(range).chunks())
+    }
+
+    fn line_count(&self) -> usize {
+        Snapshot::line_count(self)
+    }
+
+    fn line_start(&self, line: usize) -> Option<usize> {
+        Snapshot::line_start(self, line)
+    }
+
+    fn line_length(&self, line: usize) -> usize {
+        Snapshot::line_length(self, line)
+    }
+
+    fn line(&self, line: usize) -> String {
+        Snapshot::line(self, line)
+    }
+
+    fn offset_to_line_col(&self, offset: usize) -> (usize, usize) {
+        Snapshot::offset_to_line_col(self, offset)
+    }
+
+    fn line_col_to_offset(&self, line: usize, col: usize) -> Option<usize> {
+        Snapshot::line_col_to_offset(self, line, col)
+    }
+
+    fn next_grapheme_boundary(&self, offset: usize) -> usize {
+        Snapshot::next_grapheme_boundary(self, offset)
+    }
+
+    fn prev_grapheme_boundary(&self, offset: usize) -> usize {
+        Snapshot::prev_grapheme_boundary(self, offset)
+    }
+
+    fn earlier(&mut self) -> Option<usize> {
+        PieceTable::earlier(self)
+    }
+
+    fn later(&mut self) -> Option<usize> {
+        PieceTable::later(self)
+    }
+
+    fn switch_branch(&mut self) -> Option<usize> {
+        PieceTable::switch_branch(self)
+    }
+
+    fn at_time(&mut self, time: SystemTime) -> Option<usize> {
+        PieceTable::at_time(self, time)
+    }
+
+    fn set_history_limit(&mut self, limit: Option<usize>) {
+        PieceTable::set_history_limit(self, limit)
+    }
+
+    fn save_history(&self, path: &Path) -> io::Result<()> {
+        PieceTable::save_history(self, path)
+    }
+
+    fn load_history(&mut self, path: &Path) -> io::Result<bool> {
+        PieceTable::load_history(self, path)
+    }
+
+    fn is_loading(&self) -> bool {
+        PieceTable::is_loading(self)
+    }
+
+    fn poll_loading(&mut self) -> io::Result<bool> {
+        PieceTable::poll_loading(self)
+    }
+
+    fn finish_loading(&mut self) -> io::Result<()> {
+        PieceTable::finish_loading(self)
+    }
+
+    fn debug_dump(&self) -> Option<String> {
+        Some(self.dump())
+    }
+}
@@ -0,0 +1,270 @@
+use super::undo::{History, Splice};
+use super::TextBuffer;
+use crate::piece_table::{check_range, EditError};
+use std::cmp;
+use std::ops::Range;
+use std::str;
+
+/// Bytes the gap grows by beyond what an insertion needs.
+const MIN_GAP: usize = 4096;
+
+/// Text kept in one array with a gap at the last edit, so typing in one
+/// place only moves the bytes between edits. Lookups find the line in an
+/// index of the newlines and scan only that.
+pub struct GapBuffer {
+    text: Gap,
+    history: History,
+}
+
+/// The array of a `GapBuffer`, with the byte range of its gap.
+struct Gap {
+    bytes: Vec<u8>,
+    gap: Range<usize>,
+    chars: usize,
+    newlines: Newlines,
+}
+
+/// Byte and char offsets of the newlines, split at the gap like the text:
+/// those before it counted from the start of the text, those after it from
+/// the end and nearest the gap last, so neither side changes with an edit
+/// at the gap.
+#[derive(Default)]
+struct Newlines {
+    before: Vec<(usize, usize)>,
+    after: Vec<(usize, usize)>,
+}
+
+impl GapBuffer {
+    pub fn new(contents: &str) -> GapBuffer {
+        let mut bytes = Vec::with_capacity(contents.len() + MIN_GAP);
+        bytes.extend_from_slice(contents.as_bytes());
+        bytes.resize(contents.len() + MIN_GAP, 0);
+        let mut text = Gap {
+            gap: contents.len()..bytes.len(),
+            bytes,
+            chars: 0,
+            newlines: Newlines::default(),
+        };
+        text.push_newlines(contents, 0, 0);
+        text.chars = contents.chars().count();
+        GapBuffer {
+            text,
+            history: History::default(),
+        }
+    }
+}
+
+impl Gap {
+    /// The text before and after the gap.
+    fn halves(&self) -> (&str, &str) {
+        // SAFETY: only whole strings are copied in and the gap only moves
+        // to char boundaries, so both sides hold valid UTF-8
+        unsafe {
+            (
+                str::from_utf8_unchecked(&self.bytes[..self.gap.start]),
+                str::from_utf8_unchecked(&self.bytes[self.gap.end..]),
+            )
+        }
+    }
+
+    /// Length in bytes of the text without the gap.
+    fn byte_len(&self) -> usize {
+        self.bytes.len() - self.gap.len()
+    }
+
+    /// Number of newlines in the text.
+    fn newline_count(&self) -> usize {
+        self.newlines.before.len() + self.newlines.after.len()
+    }
+
+    /// Byte and char offset of the `n`th newline, counting from 0.
+    fn newline(&self, n: usize) -> Option<(usize, usize)> {
+        if let Some(&newline) = self.newlines.before.get(n) {
+            return Some(newline);
+        }
+        let after = &self.newlines.after;
+        let i = after
+            .len()
+            .checked_sub(n - self.newlines.before.len() + 1)?;
+        let (bytes, chars) = after[i];
+        Some((self.byte_len() - bytes, self.chars - chars))
+    }
+
+    /// Number of newlines before a char offset, which is the line it is on.
+    fn newlines_before(&self, offset: usize) -> usize {
+        let Newlines { before, after } = &self.newlines;
+        let from_end = self.chars - cmp::min(offset, self.chars);
+        before.partition_point(|&(_, c)| c < offset) + after.len()
+            - after.partition_point(|&(_, c)| c <= from_end)
+    }
+
+    /// Byte and char offset of the start of a line.
+    fn line_start(&self, line: usize) -> Option<(usize, usize)> {
+        match line {
+            0 => Some((0, 0)),
+            _ => self
+                .newline(line - 1)
+                .map(|(byte, char)| (byte + 1, char + 1)),
+        }
+    }
+
+    /// Byte offset of a char offset in the text without the gap, scanning
+    /// only the line it is on.
+    fn char_to_byte(&self, offset: usize) -> usize {
+        let (start, start_char) = self
+            .line_start(self.newlines_before(offset))
+            .expect("Line of an offset should exist");
+        let (before, after) = self.halves();
+        let split = before.len();
+        let after_start = start.saturating_sub(split);
+        before
+            .get(start..)
+            .unwrap_or_default()
+            .char_indices()
+            .map(|(i, _)| start + i)
+            .chain(
+                after[after_start..]
+                    .char_indices()
+                    .map(|(i, _)| split + after_start + i),
+            )
+            .nth(offset - start_char)
+            .unwrap_or(split + after.len())
+    }
+
+    /// Indexes the newlines of text inserted at a byte and char offset
+    /// before the gap.
+    fn push_newlines(&mut self, text: &str, byte: usize, char: usize) {
+        for (i, (j, c)) in text.char_indices().enumerate() {
+            if c == '\n' {
+                self.newlines.before.push((byte + j, char + i));
+            }
+        }
+    }
+
+    /// Moves the gap to a byte offset of the text without it.
+    fn move_gap(&mut self, byte: usize) {
+        let (bytes, chars) = (self.byte_len(), self.chars);
+        let Newlines { before, after } = &mut self.newlines;
+        while let Some(&(b, c)) = before.last().filter(|&&(b, _)| b >= byte) {
+            before.pop();
+            after.push((bytes - b, chars - c));
+        }
+        while let Some(&(b, c)) = after.last().filter(|&&(b, _)| bytes - b < byte) {
+            after.pop();
+            before.push((bytes - b, chars - c));
+        }
+
+        let Range { start, end } = self.gap;
+        if byte < start {
+            self.bytes.copy_within(byte..start, end - (start - byte));
+            self.gap = byte..end - (start - byte);
+        } else if byte > start {
+            let moved = byte - start;
+            self.bytes.copy_within(end..end + moved, start);
+            self.gap = start + moved..end + moved;
+        }
+    }
+
+    /// Makes the gap at least `length` bytes long.
+    fn reserve(&mut self, length: usize) {
+        if self.gap.len() >= length {
+            return;
+        }
+        let grown = length + cmp::max(MIN_GAP, self.bytes.len() / 2);
+        let after = self.bytes.len() - self.gap.end;
+        self.bytes
+            .resize(self.bytes.len() + grown - self.gap.len(), 0);
+        let new_end = self.bytes.len() - after;
+        self.bytes
+            .copy_within(self.gap.end..self.gap.end + after, new_end);
+        self.gap.end = new_end;
+    }
+}
+
+impl Splice for Gap {
+    fn splice(&mut self, range: Range<usize>, text: &str) -> String {
+        let start = self.char_to_byte(range.start);
+        let end = self.char_to_byte(range.end);
+        self.move_gap(start);
+        let removed = self.bytes[self.gap.end..self.gap.end + end - start].to_vec();
+        let removed = String::from_utf8(removed).expect("Removed text should be valid UTF-8");
+        self.gap.end += end - start;
+        let after = &mut self.newlines.after;
+        after.truncate(after.len() - removed.matches('\n').count());
+        self.reserve(text.len());
+        self.bytes[self.gap.start..self.gap.start + text.len()].copy_from_slice(text.as_bytes());
+        self.gap.start += text.len();
+        self.push_newlines(text, start, range.start);
+        self.chars = self.chars - range.len() + text.chars().count();
+        removed
+    }
+}
+
+impl TextBuffer for GapBuffer {
+    fn insert_str(&mut self, text: &str, position: usize) -> Result<(), EditError> {
+        check_range(self.text.chars, position..position)?;
+        if !text.is_empty() {
+            self.history
+                .replace(&mut self.text, position..position, text);
+        }
+        Ok(())
+    }
+
+    fn delete_range(&mut self, range: Range<usize>) -> Result<(), EditError> {
+        check_range(self.text.chars, range.clone())?;
+        if !range.is_empty() {
+            self.history.replace(&mut self.text, range, "");
+        }
+        Ok(())
+    }
+
+    fn begin_transaction(&mut self) {
+        self.history.begin();
+    }
+
+    fn commit_transaction(&mut self) {
+        self.history.commit();
+    }
+
+    fn undo(&mut self) -> Option<usize> {
+        self.history.undo(&mut self.text)
+    }
+
+    fn redo(&mut self) -> Option<usize> {
+        self.history.redo(&mut self.text)
+    }
+
+    fn len(&self) -> usize {
+        self.text.chars
+    }
+
+    fn chunks(&self, range: Range<usize>) -> Box<dyn Iterator<Item = &str> + '_> {
+        let end = self.text.char_to_byte(range.end);
+        let start = cmp::min(self.text.char_to_byte(range.start), end);
+        let (before, after) = self.text.halves();
+        let split = before.len();
+        let first = &before[cmp::min(start, split)..cmp::min(end, split)];
+        let second = &after[start.saturating_sub(split)..end.saturating_sub(split)];
+        Box::new(
+            [first, second]
+                .into_iter()
+                .filter(|chunk| !chunk.is_empty()),
+        )
+    }
+
+    fn line_count(&self) -> usize {
+        self.text.newline_count() + 1
+    }
+
+    fn line_start(&self, line: usize) -> Option<usize> {
+        self.text.line_start(line).map(|(_, char)| char)
+    }
+
+    fn offset_to_line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.text.newlines_before(offset);
+        let start = self
+            .line_start(line)
+            .expect("Line of an offset should exist");
+        (line, cmp::min(offset, self.text.chars) - start)
+    }
+}
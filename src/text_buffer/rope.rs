@@ -0,0 +1,92 @@
+use super::undo::{History, Splice};
+use super::TextBuffer;
+use crate::piece_table::{check_range, EditError};
+use ropey::Rope;
+use std::cmp;
+use std::ops::Range;
+
+/// Text kept in a rope, a balanced tree of small chunks.
+pub struct RopeBuffer {
+    text: Rope,
+    history: History,
+}
+
+impl RopeBuffer {
+    pub fn new(contents: &str) -> RopeBuffer {
+        RopeBuffer {
+            text: Rope::from_str(contents),
+            history: History::default(),
+        }
+    }
+}
+
+impl Splice for Rope {
+    fn splice(&mut self, range: Range<usize>, text: &str) -> String {
+        let removed = self.slice(range.clone()).to_string();
+        self.remove(range.clone());
+        self.insert(range.start, text);
+        removed
+    }
+}
+
+impl TextBuffer for RopeBuffer {
+    fn insert_str(&mut self, text: &str, position: usize) -> Result<(), EditError> {
+        check_range(self.text.len_chars(), position..position)?;
+        if !text.is_empty() {
+            self.history
+                .replace(&mut self.text, position..position, text);
+        }
+        Ok(())
+    }
+
+    fn delete_range(&mut self, range: Range<usize>) -> Result<(), EditError> {
+        check_range(self.text.len_chars(), range.clone())?;
+        if !range.is_empty() {
+            self.history.replace(&mut self.text, range, "");
+        }
+        Ok(())
+    }
+
+    fn begin_transaction(&mut self) {
+        self.history.begin();
+    }
+
+    fn commit_transaction(&mut self) {
+        self.history.commit();
+    }
+
+    fn undo(&mut self) -> Option<usize> {
+        self.history.undo(&mut self.text)
+    }
+
+    fn redo(&mut self) -> Option<usize> {
+        self.history.redo(&mut self.text)
+    }
+
+    fn len(&self) -> usize {
+        self.text.len_chars()
+    }
+
+    fn chunks(&self, range: Range<usize>) -> Box<dyn Iterator<Item = &str> + '_> {
+        let end = cmp::min(range.end, self.text.len_chars());
+        let start = cmp::min(range.start, end);
+        Box::new(self.text.slice(start..end).chunks())
+    }
+
+    fn line_count(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    fn line_start(&self, line: usize) -> Option<usize> {
+        if line >= self.text.len_lines() {
+            return None;
+        }
+        Some(self.text.line_to_char(line))
+    }
+
+    fn offset_to_line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = cmp::min(offset, self.text.len_chars());
+        let line = self.text.char_to_line(offset);
+        (line, offset - self.text.line_to_char(line))
+    }
+}
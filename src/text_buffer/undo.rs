@@ -0,0 +1,78 @@
+use std::mem;
+use std::ops::Range;
+
+/// Text that replaced other text at a char offset.
+pub(super) struct Edit {
+    position: usize,
+    removed: String,
+    inserted: String,
+}
+
+/// A buffer that can replace a char range without recording it.
+pub(super) trait Splice {
+    /// Replaces a range with some text and returns the removed text.
+    fn splice(&mut self, range: Range<usize>, text: &str) -> String;
+}
+
+/// Linear undo log of the simple backends: undoing and then editing
+/// drops the undone steps.
+#[derive(Default)]
+pub(super) struct History {
+    done: Vec<Vec<Edit>>,
+    undone: Vec<Vec<Edit>>,
+    pending: Vec<Edit>,
+    depth: usize,
+}
+
+impl History {
+    pub(super) fn begin(&mut self) {
+        self.depth += 1;
+    }
+
+    pub(super) fn commit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 && !self.pending.is_empty() {
+            self.done.push(mem::take(&mut self.pending));
+        }
+    }
+
+    /// Replaces a range of `buffer` with some text and logs it as an edit
+    /// of the current transaction, or as its own undo step outside one.
+    pub(super) fn replace(&mut self, buffer: &mut impl Splice, range: Range<usize>, text: &str) {
+        let position = range.start;
+        let removed = buffer.splice(range, text);
+        self.undone.clear();
+        self.pending.push(Edit {
+            position,
+            removed,
+            inserted: text.to_string(),
+        });
+        if self.depth == 0 {
+            self.done.push(mem::take(&mut self.pending));
+        }
+    }
+
+    pub(super) fn undo(&mut self, buffer: &mut impl Splice) -> Option<usize> {
+        let edits = self.done.pop()?;
+        for edit in edits.iter().rev() {
+            let range = edit.position..edit.position + edit.inserted.chars().count();
+            buffer.splice(range, &edit.removed);
+        }
+        let position = edits.first().map(|edit| edit.position);
+        self.undone.push(edits);
+        position
+    }
+
+    pub(super) fn redo(&mut self, buffer: &mut impl Splice) -> Option<usize> {
+        let edits = self.undone.pop()?;
+        for edit in edits.iter() {
+            let range = edit.position..edit.position + edit.removed.chars().count();
+            buffer.splice(range, &edit.inserted);
+        }
+        let position = edits
+            .last()
+            .map(|edit| edit.position + edit.inserted.chars().count());
+        self.done.push(edits);
+        position
+    }
+}